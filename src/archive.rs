@@ -0,0 +1,359 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+    thread,
+};
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use tar::{Archive, EntryType};
+
+/// How many bytes are read between two progress messages.
+const PROGRESS_STEP: u64 = 64 * 1024 * 1024;
+
+/// A reader which logs how many bytes have been read so far.
+pub(crate) struct ProgressReader<R: Read> {
+    inner:       R,
+    name:        String,
+    read:        u64,
+    next_report: u64,
+}
+
+impl<R: Read> ProgressReader<R> {
+    #[inline]
+    pub(crate) fn new<S: Into<String>>(inner: R, name: S) -> Self {
+        ProgressReader {
+            inner,
+            name: name.into(),
+            read: 0,
+            next_report: PROGRESS_STEP,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let c = self.inner.read(buf)?;
+
+        self.read += c as u64;
+
+        if self.read >= self.next_report {
+            log::info!("{name}: {size} MiB processed", name = self.name, size = self.read >> 20);
+
+            self.next_report += PROGRESS_STEP;
+        }
+
+        Ok(c)
+    }
+}
+
+/// Drop the first component of a path in an archive. Returns `None` if nothing is left.
+fn strip_first_component(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    // `Path::components` only keeps a leading `.`, which is then dropped as the first component
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!("The archive contains an unsafe path {path:?}"));
+    }
+
+    let relative_path: PathBuf = path.components().skip(1).collect();
+
+    if relative_path.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(relative_path))
+}
+
+/// Extract a gzipped tarball into `directory` and drop the first component of every path, like
+/// `tar --strip-components 1 -z -x`. Returns the number of extracted entries.
+///
+/// Like GNU `tar`, nothing is written outside `directory`: symbolic links which point to an
+/// absolute path or through `..` are rejected, and hard links have to point to an entry of the
+/// archive.
+pub(crate) fn extract_tar_gz<R: Read>(reader: R, directory: &Path) -> anyhow::Result<usize> {
+    let mut archive = Archive::new(GzDecoder::new(reader));
+
+    let mut count = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        let entry_type = entry.header().entry_type();
+
+        if matches!(entry_type, EntryType::XGlobalHeader) {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+
+        let relative_path = match strip_first_component(path.as_path())? {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
+
+        let target_path = directory.join(relative_path.as_path());
+
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if entry_type.is_hard_link() || entry_type.is_symlink() {
+            let link_name = match entry.link_name()? {
+                Some(link_name) => link_name.into_owned(),
+                None => return Err(anyhow!("The link {path:?} in the archive has no target")),
+            };
+
+            if entry_type.is_hard_link() {
+                // the target is another entry of the archive, with the first component as well
+                let link_target = match strip_first_component(link_name.as_path())? {
+                    Some(link_target) => directory.join(link_target),
+                    None => {
+                        return Err(anyhow!(
+                            "The archive contains an unsafe link {path:?} -> {link_name:?}"
+                        ))
+                    },
+                };
+
+                fs::hard_link(link_target, target_path.as_path())
+                    .map_err(|err| anyhow!("Cannot extract {relative_path:?}: {err}"))?;
+
+                count += 1;
+
+                continue;
+            }
+
+            if !link_name
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(anyhow!(
+                    "The archive contains an unsafe link {path:?} -> {link_name:?}"
+                ));
+            }
+        }
+
+        entry
+            .unpack(target_path.as_path())
+            .map_err(|err| anyhow!("Cannot extract {relative_path:?}: {err}"))?;
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Open `<deploy_dir>/<name>.tar.zst` (decompressed on the fly) or `<deploy_dir>/<name>.tar`.
+pub(crate) fn open_tarball(deploy_dir: &Path, name: &str) -> anyhow::Result<Box<dyn Read + Send>> {
+    let zstd_path = deploy_dir.join(format!("{name}.tar.zst"));
+
+    if zstd_path.is_file() {
+        let decoder = zstd::Decoder::new(File::open(zstd_path)?)?;
+
+        return Ok(Box::new(ProgressReader::new(decoder, format!("{name}.tar.zst"))));
+    }
+
+    let tar_path = deploy_dir.join(format!("{name}.tar"));
+
+    if tar_path.is_file() {
+        let file = BufReader::new(File::open(tar_path)?);
+
+        return Ok(Box::new(ProgressReader::new(file, format!("{name}.tar"))));
+    }
+
+    Err(anyhow!("deploy/{name}.tar.zst cannot be found in the project."))
+}
+
+/// Make sure that `<deploy_dir>/<name>.tar.zst` exists. If the build only produced
+/// `<deploy_dir>/<name>.tar`, it is compressed with the given level and number of threads (`0`
+/// means one thread per CPU).
+pub(crate) fn ensure_zstd_tarball(
+    deploy_dir: &Path,
+    name: &str,
+    level: i32,
+    threads: u32,
+) -> anyhow::Result<()> {
+    let zstd_path = deploy_dir.join(format!("{name}.tar.zst"));
+
+    if zstd_path.is_file() {
+        return Ok(());
+    }
+
+    let tar_path = deploy_dir.join(format!("{name}.tar"));
+
+    if !tar_path.is_file() {
+        return Err(anyhow!("deploy/{name}.tar.zst cannot be found in the project."));
+    }
+
+    let threads = if threads == 0 {
+        thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1)
+    } else {
+        threads
+    };
+
+    log::info!("Compressing deploy/{name}.tar (level {level}, {threads} threads)");
+
+    let compress = || -> anyhow::Result<()> {
+        let mut encoder = zstd::Encoder::new(File::create(zstd_path.as_path())?, level)?;
+
+        encoder.multithread(threads)?;
+
+        let mut reader =
+            ProgressReader::new(BufReader::new(File::open(tar_path.as_path())?), "compression");
+
+        io::copy(&mut reader, &mut encoder)?;
+
+        encoder.finish()?;
+
+        Ok(())
+    };
+
+    if let Err(err) = compress() {
+        let _ = fs::remove_file(zstd_path.as_path());
+
+        return Err(anyhow!("Cannot compress deploy/{name}.tar: {err}"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use tar::{Builder, Header};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Build a header with the path and link name written as is, so that unsafe paths, which
+    /// `Header::set_path` refuses, can be tested too.
+    fn header(entry_type: EntryType, path: &str, link_name: &str, size: u64) -> Header {
+        let mut header = Header::new_old();
+
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.as_old_mut().linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(size);
+        header.set_cksum();
+
+        header
+    }
+
+    /// A gzipped tarball of `(entry type, path, link name, content)` entries.
+    fn tar_gz(entries: &[(EntryType, &str, &str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+
+        for (entry_type, path, link_name, content) in entries {
+            let header = header(*entry_type, path, link_name, content.len() as u64);
+
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn extract_strips_first_component() {
+        let dir = tempdir().unwrap();
+
+        let archive = tar_gz(&[
+            (EntryType::Directory, "project-0b14cd4f/", "", ""),
+            (EntryType::Regular, "project-0b14cd4f/index.html", "", "index"),
+            (EntryType::Regular, "project-0b14cd4f/css/main.css", "", "css"),
+            (EntryType::Symlink, "project-0b14cd4f/css/style.css", "main.css", ""),
+            (EntryType::Link, "project-0b14cd4f/home.html", "project-0b14cd4f/index.html", ""),
+        ]);
+
+        assert_eq!(4, extract_tar_gz(archive.as_slice(), dir.path()).unwrap());
+
+        assert_eq!("index", fs::read_to_string(dir.path().join("index.html")).unwrap());
+        assert_eq!("css", fs::read_to_string(dir.path().join("css/main.css")).unwrap());
+        assert_eq!("css", fs::read_to_string(dir.path().join("css/style.css")).unwrap());
+        assert_eq!("index", fs::read_to_string(dir.path().join("home.html")).unwrap());
+        assert!(!dir.path().join("project-0b14cd4f").exists());
+    }
+
+    #[test]
+    fn extract_rejects_unsafe_paths() {
+        for path in ["project/../../escape", "/project/escape", "project/a/../../escape"] {
+            let dir = tempdir().unwrap();
+            let archive = tar_gz(&[(EntryType::Regular, path, "", "escape")]);
+
+            let err = extract_tar_gz(archive.as_slice(), dir.path()).unwrap_err();
+
+            assert!(err.to_string().contains("unsafe path"), "{path}: {err}");
+        }
+    }
+
+    #[test]
+    fn extract_rejects_symlink_escape() {
+        let outside = tempdir().unwrap();
+        let outside_path = outside.path().to_str().unwrap();
+
+        for link_name in [outside_path, "../..", "a/../../.."] {
+            let dir = tempdir().unwrap();
+
+            let archive = tar_gz(&[
+                (EntryType::Symlink, "project/a/link", link_name, ""),
+                (EntryType::Regular, "project/a/link/passwd", "", "escape"),
+            ]);
+
+            let err = extract_tar_gz(archive.as_slice(), dir.path()).unwrap_err();
+
+            assert!(err.to_string().contains("unsafe link"), "{link_name}: {err}");
+        }
+
+        assert!(!outside.path().join("passwd").exists());
+
+        let dir = tempdir().unwrap();
+        let archive = tar_gz(&[(EntryType::Link, "project/passwd", "/etc/passwd", "")]);
+
+        let err = extract_tar_gz(archive.as_slice(), dir.path()).unwrap_err();
+
+        assert!(err.to_string().contains("unsafe path"), "{err}");
+    }
+
+    #[test]
+    fn compress_and_open_tarball() {
+        let dir = tempdir().unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+
+        header.set_size(5);
+        header.set_cksum();
+        builder.append_data(&mut header, "image.json", "image".as_bytes()).unwrap();
+
+        let tar = builder.into_inner().unwrap();
+
+        assert!(open_tarball(dir.path(), "image").is_err());
+        assert!(ensure_zstd_tarball(dir.path(), "image", 3, 1).is_err());
+
+        fs::write(dir.path().join("image.tar"), tar.as_slice()).unwrap();
+
+        let mut content = Vec::new();
+
+        open_tarball(dir.path(), "image").unwrap().read_to_end(&mut content).unwrap();
+
+        assert_eq!(tar, content);
+
+        ensure_zstd_tarball(dir.path(), "image", 3, 0).unwrap();
+
+        assert!(dir.path().join("image.tar.zst").is_file());
+
+        // `.tar.zst` is preferred over `.tar`
+        fs::remove_file(dir.path().join("image.tar")).unwrap();
+
+        let mut content = Vec::new();
+
+        open_tarball(dir.path(), "image").unwrap().read_to_end(&mut content).unwrap();
+
+        assert_eq!(tar, content);
+
+        // an existing `.tar.zst` is kept
+        ensure_zstd_tarball(dir.path(), "image", 3, 1).unwrap();
+    }
+}
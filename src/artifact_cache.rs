@@ -0,0 +1,659 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use tempfile::Builder;
+use trim_in_place::TrimInPlace;
+use validators::prelude::*;
+
+use crate::{
+    checksum::{check_sha256sums, compute_sha256sums},
+    functions::{is_dry_run, plan},
+    models::*,
+};
+
+/// The checksums of the files of an entry, in the format of `sha256sum`. It is written last, so an
+/// entry without it is incomplete.
+pub(crate) const MANIFEST_FILE: &str = "SHA256SUMS";
+/// The Unix time in seconds when the entry was stored or last used.
+const LAST_USED_FILE: &str = "last-used";
+const IMAGE_NAME_FILE: &str = "image-name.txt";
+const DOCKER_REGISTRY_FILE: &str = "docker-registry.txt";
+const PUBLIC_NAME_FILE: &str = "public-name.txt";
+const DOCKER_COMPOSE_FILE: &str = "docker-compose.yml";
+
+/// The directory name of an entry without a build target. A build target cannot contain `@`.
+const NO_BUILD_TARGET: &str = "@none";
+/// The prefix of the directory where an entry is prepared. A build target cannot contain `.`, so it
+/// is never taken for an entry.
+const TEMP_PREFIX: &str = ".tmp-";
+
+/// A local cache of built artifacts. Every entry is the directory
+/// `<cache>/<project id>/<commit sha>/<build target>`.
+#[derive(Debug)]
+pub(crate) struct ArtifactCache {
+    directory:  PathBuf,
+    /// In bytes.
+    max_size:   u64,
+    max_age:    Duration,
+    /// The Unix time in seconds when this run started. Entries used since then may be in use by
+    /// another run, so they are not evicted.
+    started_at: u64,
+}
+
+/// The artifacts of a backend release.
+#[derive(Debug)]
+pub(crate) struct BackArtifacts {
+    /// The directory which holds the `<image>.tar.zst` files (unless they were pushed to a
+    /// registry).
+    pub(crate) directory:      PathBuf,
+    pub(crate) image_names:    Vec<ImageName>,
+    /// The rewritten docker compose file.
+    pub(crate) docker_compose: String,
+}
+
+impl ArtifactCache {
+    /// `max_size` is in MiB and `max_age` is in days.
+    #[inline]
+    pub(crate) fn new(directory: PathBuf, max_size: u64, max_age: u64) -> Self {
+        ArtifactCache {
+            directory,
+            max_size: max_size.saturating_mul(1024 * 1024),
+            max_age: Duration::from_secs(max_age.saturating_mul(24 * 60 * 60)),
+            started_at: get_timestamp(),
+        }
+    }
+
+    fn entry_path(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: Option<&BuildTarget>,
+    ) -> PathBuf {
+        self.directory.join(project_id.to_string()).join(commit_sha.get_sha()).join(
+            match build_target {
+                Some(build_target) => build_target.as_ref(),
+                None => NO_BUILD_TARGET,
+            },
+        )
+    }
+
+    /// Evict old entries, and then find a complete entry and check its files against the manifest.
+    /// A corrupted entry is removed.
+    pub(crate) fn get(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: Option<&BuildTarget>,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let entry_path = self.entry_path(project_id, commit_sha, build_target);
+
+        // a cache which is only read from expires its entries too
+        self.evict(entry_path.as_path())?;
+
+        let manifest = match fs::read_to_string(entry_path.join(MANIFEST_FILE)) {
+            Ok(manifest) => manifest,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // marked as used before the check, so that another run does not evict it meanwhile
+        if !is_dry_run() {
+            write_last_used(entry_path.as_path())?;
+        }
+
+        if !check_sha256sums(entry_path.as_path(), manifest.as_str())? {
+            log::warn!("The cached artifacts in {entry_path:?} are corrupted, so they are removed");
+
+            if !plan(format_args!("remove {entry_path:?}")) {
+                remove_entry(entry_path.as_path())?;
+            }
+
+            return Ok(None);
+        }
+
+        log::info!("Found the cached artifacts in {entry_path:?}");
+
+        Ok(Some(entry_path))
+    }
+
+    /// Store the files as an entry, replacing the old one, and then evict old entries.
+    /// `contents` are written as files of the entry. The old entry is moved aside and removed
+    /// after the new one is in place, and a crash in between leaves it to be removed by a later
+    /// eviction as a directory of an interrupted store.
+    fn put(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: Option<&BuildTarget>,
+        files: &[PathBuf],
+        contents: &[(&str, &str)],
+    ) -> anyhow::Result<()> {
+        let entry_path = self.entry_path(project_id, commit_sha, build_target);
+
+        if plan(format_args!("store the artifacts in {entry_path:?}")) {
+            return Ok(());
+        }
+
+        log::info!("Storing the artifacts in {entry_path:?}");
+
+        let sha_path = entry_path.parent().unwrap();
+
+        fs::create_dir_all(sha_path)?;
+
+        // prepared next to the entry, so that it can be renamed into place
+        let temp_dir = Builder::new().prefix(TEMP_PREFIX).tempdir_in(sha_path)?;
+        let temp_path = temp_dir.path();
+
+        let mut file_names = Vec::with_capacity(files.len() + contents.len());
+
+        for file in files {
+            let file_name = file
+                .file_name()
+                .ok_or_else(|| anyhow!("{file:?} is not a file"))?
+                .to_string_lossy()
+                .into_owned();
+
+            fs::copy(file, temp_path.join(file_name.as_str()))?;
+
+            file_names.push(file_name);
+        }
+
+        for (file_name, content) in contents {
+            fs::write(temp_path.join(file_name), content)?;
+
+            file_names.push(String::from(*file_name));
+        }
+
+        let manifest = compute_sha256sums(temp_path, &file_names)?;
+
+        write_last_used(temp_path)?;
+
+        fs::write(temp_path.join(MANIFEST_FILE), manifest)?;
+
+        let old_dir = Builder::new().prefix(TEMP_PREFIX).tempdir_in(sha_path)?;
+
+        match fs::rename(entry_path.as_path(), old_dir.path().join("entry")) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Err(err) = fs::rename(temp_path, entry_path.as_path()) {
+            // another process may have stored the same artifacts in the meantime
+            if entry_path.join(MANIFEST_FILE).is_file() {
+                log::info!("The artifacts in {entry_path:?} have been stored by another process");
+            } else {
+                return Err(err.into());
+            }
+        }
+
+        old_dir.close()?;
+
+        self.evict(entry_path.as_path())
+    }
+
+    /// Remove the entries which have not been used within the maximum age, and then the least
+    /// recently used entries until the cache fits in the maximum size. `keep` and the entries used
+    /// since this run started are never removed, because another run may be reading them. A
+    /// directory left by an interrupted store is removed once it is older than the maximum age.
+    fn evict(&self, keep: &Path) -> anyhow::Result<()> {
+        let now = get_timestamp();
+
+        let mut entries: Vec<(PathBuf, u64, u64)> = Vec::new();
+        let mut temp_paths: Vec<PathBuf> = Vec::new();
+
+        for project_dir in read_dirs(self.directory.as_path())? {
+            for sha_dir in read_dirs(project_dir.as_path())? {
+                for entry_path in read_dirs(sha_dir.as_path())? {
+                    if entry_path.file_name().unwrap().to_string_lossy().starts_with(TEMP_PREFIX) {
+                        let modified = fs::metadata(entry_path.as_path())?
+                            .modified()?
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs())
+                            .unwrap_or(0);
+
+                        if now.saturating_sub(modified) > self.max_age.as_secs() {
+                            temp_paths.push(entry_path);
+                        }
+
+                        continue;
+                    }
+
+                    let last_used = fs::read_to_string(entry_path.join(LAST_USED_FILE))
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u64>().ok())
+                        .unwrap_or(0);
+
+                    let size = get_size(entry_path.as_path())?;
+
+                    entries.push((entry_path, last_used, size));
+                }
+            }
+        }
+
+        for temp_path in temp_paths {
+            if plan(format_args!("remove {temp_path:?}")) {
+                continue;
+            }
+
+            log::info!("Removing {temp_path:?} left by an interrupted store");
+
+            fs::remove_dir_all(temp_path.as_path())?;
+        }
+
+        // the least recently used first
+        entries.sort_by_key(|(_, last_used, _)| *last_used);
+
+        let mut total_size: u64 = entries.iter().map(|(_, _, size)| size).sum();
+
+        for (entry_path, last_used, size) in entries {
+            if entry_path == keep || last_used >= self.started_at {
+                continue;
+            }
+
+            let is_expired = now.saturating_sub(last_used) > self.max_age.as_secs();
+
+            if !is_expired && total_size <= self.max_size {
+                continue;
+            }
+
+            total_size -= size;
+
+            if plan(format_args!("evict {entry_path:?}")) {
+                continue;
+            }
+
+            log::info!("Evicting the cached artifacts in {entry_path:?}");
+
+            remove_entry(entry_path.as_path())?;
+
+            // remove the parent directories if they become empty
+            for parent in entry_path.ancestors().skip(1).take(2) {
+                if fs::remove_dir(parent).is_err() {
+                    break;
+                }
+            }
+        }
+
+        if total_size > self.max_size {
+            log::warn!(
+                "The artifact cache is still larger than {max_size} MiB because of the artifacts \
+                 in use",
+                max_size = self.max_size / 1024 / 1024
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Find the artifacts of a backend release. An entry built for another registry (or without
+    /// one) is not used.
+    pub(crate) fn get_back_artifacts(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: Option<&BuildTarget>,
+        docker_registry: Option<&DockerRegistry>,
+    ) -> anyhow::Result<Option<BackArtifacts>> {
+        let entry_path = match self.get(project_id, commit_sha, build_target)? {
+            Some(entry_path) => entry_path,
+            None => return Ok(None),
+        };
+
+        let cached_docker_registry = match fs::read_to_string(entry_path.join(DOCKER_REGISTRY_FILE))
+        {
+            Ok(cached_docker_registry) => cached_docker_registry,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                log::info!("The cached artifacts are not of a backend, so they are not used");
+
+                return Ok(None);
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        if cached_docker_registry.trim() != docker_registry.map(|r| r.as_ref()).unwrap_or_default()
+        {
+            log::info!(
+                "The cached artifacts are for another docker registry, so they are not used"
+            );
+
+            return Ok(None);
+        }
+
+        let mut image_names = Vec::new();
+
+        for line in fs::read_to_string(entry_path.join(IMAGE_NAME_FILE))?.lines() {
+            image_names.push(
+                ImageName::parse_str(line)
+                    .map_err(|_| anyhow!("{line:?} in the artifact cache is not an image name"))?,
+            );
+        }
+
+        let docker_compose = fs::read_to_string(entry_path.join(DOCKER_COMPOSE_FILE))?;
+
+        Ok(Some(BackArtifacts {
+            directory: entry_path,
+            image_names,
+            docker_compose,
+        }))
+    }
+
+    /// Store the artifacts of a backend release. The image tarballs are only stored without a
+    /// registry, because the images are in the registry otherwise.
+    pub(crate) fn put_back_artifacts(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: Option<&BuildTarget>,
+        docker_registry: Option<&DockerRegistry>,
+        back_artifacts: &BackArtifacts,
+    ) -> anyhow::Result<()> {
+        let files: Vec<PathBuf> = if docker_registry.is_some() {
+            Vec::new()
+        } else {
+            back_artifacts
+                .image_names
+                .iter()
+                .map(|image_name| {
+                    back_artifacts
+                        .directory
+                        .join(format!("{image_name}.tar.zst", image_name = image_name.as_ref()))
+                })
+                .collect()
+        };
+
+        let image_names = back_artifacts
+            .image_names
+            .iter()
+            .map(|image_name| image_name.as_ref())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        self.put(project_id, commit_sha, build_target, &files, &[
+            (IMAGE_NAME_FILE, image_names.as_str()),
+            (DOCKER_REGISTRY_FILE, docker_registry.map(|r| r.as_ref()).unwrap_or_default()),
+            (DOCKER_COMPOSE_FILE, back_artifacts.docker_compose.as_str()),
+        ])
+    }
+
+    /// Find the artifacts of a frontend release, returning the directory which holds
+    /// `<public_name>.tar.zst` and the public name.
+    pub(crate) fn get_front_artifacts(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: &BuildTarget,
+    ) -> anyhow::Result<Option<(PathBuf, Name)>> {
+        let entry_path = match self.get(project_id, commit_sha, Some(build_target))? {
+            Some(entry_path) => entry_path,
+            None => return Ok(None),
+        };
+
+        let mut public_name = match fs::read_to_string(entry_path.join(PUBLIC_NAME_FILE)) {
+            Ok(public_name) => public_name,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                log::info!("The cached artifacts are not of a frontend, so they are not used");
+
+                return Ok(None);
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        public_name.trim_in_place();
+
+        let public_name = Name::parse_string(public_name)
+            .map_err(|_| anyhow!("The public name in the artifact cache is not correct"))?;
+
+        Ok(Some((entry_path, public_name)))
+    }
+
+    /// Store the archive of public static files of a frontend release.
+    pub(crate) fn put_front_artifacts(
+        &self,
+        project_id: u64,
+        commit_sha: &CommitSha,
+        build_target: &BuildTarget,
+        deploy_dir: &Path,
+        public_name: &Name,
+    ) -> anyhow::Result<()> {
+        self.put(
+            project_id,
+            commit_sha,
+            Some(build_target),
+            &[deploy_dir
+                .join(format!("{public_name}.tar.zst", public_name = public_name.as_ref()))],
+            &[(PUBLIC_NAME_FILE, public_name.as_ref())],
+        )
+    }
+}
+
+#[inline]
+fn get_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[inline]
+fn write_last_used(entry_path: &Path) -> anyhow::Result<()> {
+    fs::write(entry_path.join(LAST_USED_FILE), get_timestamp().to_string())?;
+
+    Ok(())
+}
+
+/// Remove an entry, which another run may have removed already.
+fn remove_entry(entry_path: &Path) -> anyhow::Result<()> {
+    match fs::remove_dir_all(entry_path) {
+        Ok(()) => Ok(()),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// List the sub-directories. A directory which does not exist has none.
+fn read_dirs(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let read_dir = match fs::read_dir(path) {
+        Ok(read_dir) => read_dir,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut dirs = Vec::new();
+
+    for entry in read_dir {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn get_size(path: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        size += if metadata.is_dir() { get_size(entry.path().as_path())? } else { metadata.len() };
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn cache(directory: &Path, max_size: u64, max_age: Duration) -> ArtifactCache {
+        ArtifactCache {
+            directory: directory.to_path_buf(),
+            max_size,
+            max_age,
+            started_at: get_timestamp(),
+        }
+    }
+
+    fn sha(c: char) -> CommitSha {
+        CommitSha::parse_string(c.to_string().repeat(40)).unwrap()
+    }
+
+    /// Store an entry with a file of `size` bytes.
+    fn put(cache: &ArtifactCache, project_id: u64, commit_sha: &CommitSha, size: usize) -> PathBuf {
+        let source = tempdir().unwrap();
+        let file = source.path().join("app.tar.zst");
+
+        fs::write(file.as_path(), vec![b'0'; size]).unwrap();
+
+        cache.put(project_id, commit_sha, None, &[file], &[(IMAGE_NAME_FILE, "app")]).unwrap();
+
+        cache.entry_path(project_id, commit_sha, None)
+    }
+
+    /// Pretend that an entry was last used by an earlier run.
+    fn set_last_used(entry_path: &Path, seconds_ago: u64) {
+        fs::write(entry_path.join(LAST_USED_FILE), (get_timestamp() - seconds_ago).to_string())
+            .unwrap();
+    }
+
+    fn new_cache() -> (TempDir, ArtifactCache) {
+        let dir = tempdir().unwrap();
+        let cache = cache(dir.path(), u64::MAX, Duration::from_secs(30 * DAY));
+
+        (dir, cache)
+    }
+
+    #[test]
+    fn put_and_get() {
+        let (_dir, cache) = new_cache();
+        let commit_sha = sha('a');
+
+        assert!(cache.get(1, &commit_sha, None).unwrap().is_none());
+
+        let entry_path = put(&cache, 1, &commit_sha, 10);
+
+        assert_eq!(Some(entry_path.clone()), cache.get(1, &commit_sha, None).unwrap());
+        assert_eq!("0000000000", fs::read_to_string(entry_path.join("app.tar.zst")).unwrap());
+        assert_eq!("app", fs::read_to_string(entry_path.join(IMAGE_NAME_FILE)).unwrap());
+
+        // replacing an entry leaves nothing of the old one behind
+        put(&cache, 1, &commit_sha, 5);
+
+        assert_eq!("00000", fs::read_to_string(entry_path.join("app.tar.zst")).unwrap());
+        assert_eq!(1, fs::read_dir(entry_path.parent().unwrap()).unwrap().count());
+
+        assert!(cache.get(1, &sha('b'), None).unwrap().is_none());
+        assert!(cache.get(2, &commit_sha, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn remove_corrupted() {
+        let (_dir, cache) = new_cache();
+        let commit_sha = sha('a');
+
+        let entry_path = put(&cache, 1, &commit_sha, 10);
+
+        fs::write(entry_path.join("app.tar.zst"), "corrupted").unwrap();
+
+        assert!(cache.get(1, &commit_sha, None).unwrap().is_none());
+        assert!(!entry_path.exists());
+
+        // an entry without its manifest is incomplete
+        let entry_path = put(&cache, 1, &commit_sha, 10);
+
+        fs::remove_file(entry_path.join(MANIFEST_FILE)).unwrap();
+
+        assert!(cache.get(1, &commit_sha, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dir = tempdir().unwrap();
+        // about two entries of 1000 bytes with their manifests
+        let cache = cache(dir.path(), 2500, Duration::from_secs(30 * DAY));
+
+        let a = put(&cache, 1, &sha('a'), 1000);
+        let b = put(&cache, 1, &sha('b'), 1000);
+
+        set_last_used(a.as_path(), 300);
+        set_last_used(b.as_path(), 200);
+
+        let c = put(&cache, 1, &sha('c'), 1000);
+
+        assert!(!a.exists());
+        assert!(b.exists());
+        assert!(c.exists());
+    }
+
+    #[test]
+    fn evict_expired() {
+        let dir = tempdir().unwrap();
+        let cache = cache(dir.path(), u64::MAX, Duration::from_secs(DAY));
+
+        let a = put(&cache, 1, &sha('a'), 10);
+        let b = put(&cache, 2, &sha('b'), 10);
+
+        set_last_used(a.as_path(), 2 * DAY);
+        set_last_used(b.as_path(), DAY / 2);
+
+        put(&cache, 3, &sha('c'), 10);
+
+        assert!(!a.exists());
+        // the empty directories of the project are removed too
+        assert!(!dir.path().join("1").exists());
+        assert!(b.exists());
+    }
+
+    #[test]
+    fn evict_never_removes_entries_in_use() {
+        let dir = tempdir().unwrap();
+        let cache = cache(dir.path(), 0, Duration::from_secs(DAY));
+
+        let a = put(&cache, 1, &sha('a'), 10);
+
+        set_last_used(a.as_path(), 2 * DAY);
+
+        // the entry which is returned is kept, even if it is expired and the cache is too large
+        assert_eq!(Some(a.clone()), cache.get(1, &sha('a'), None).unwrap());
+
+        // another run has used the entry since this run started
+        let b = put(&cache, 1, &sha('b'), 10);
+
+        assert!(a.exists());
+        assert!(b.exists());
+    }
+
+    #[test]
+    fn remove_interrupted_stores() {
+        let dir = tempdir().unwrap();
+        let sha_path = dir.path().join("1").join(sha('a').get_sha());
+        let temp_path = sha_path.join(format!("{TEMP_PREFIX}interrupted"));
+
+        fs::create_dir_all(temp_path.as_path()).unwrap();
+        fs::write(temp_path.join("app.tar.zst"), "0").unwrap();
+
+        // a directory which may still be in use by another run is kept
+        cache(dir.path(), u64::MAX, Duration::from_secs(DAY)).evict(Path::new("")).unwrap();
+
+        assert!(temp_path.exists());
+
+        // the modification time is in seconds
+        thread::sleep(Duration::from_millis(1100));
+
+        cache(dir.path(), u64::MAX, Duration::ZERO).evict(Path::new("")).unwrap();
+
+        assert!(!temp_path.exists());
+    }
+}
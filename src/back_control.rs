@@ -1,14 +1,14 @@
-use std::{fmt::Write, process::Stdio};
+use std::{fmt::Write, num::NonZeroUsize};
 
 use anyhow::anyhow;
-use execute::Execute;
-use trim_in_place::TrimInPlace;
 
 use crate::{
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    inventory::ProjectInventory,
     models::*,
+    shell::quote,
 };
 
 pub(crate) fn back_control(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -21,115 +21,301 @@ pub(crate) fn back_control(cli_args: CLIArgs) -> anyhow::Result<()> {
         reference_name,
         phase,
         command,
+        batch_size,
+        batch_percent,
+        auto_rollback,
+        ..
     } = cli_args.command
     {
         check_ssh()?;
 
-        let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
+        let ProjectInventory {
+            ssh_user_hosts,
+            health_check,
+        } = find_project_inventory(phase, project_id)?;
 
         if ssh_user_hosts.is_empty() {
             log::warn!("No hosts to control!");
             return Ok(());
         }
 
-        for ssh_user_host in ssh_user_hosts.iter() {
-            log::info!("Controlling to {ssh_user_host} ({command})", command = command.as_str());
+        let release = format!(
+            "{reference_name}-{commit_sha}",
+            reference_name = reference_name.as_ref(),
+            commit_sha = commit_sha.get_short_sha(),
+        );
 
-            let ssh_root = {
-                let mut ssh_home = get_ssh_home(ssh_user_host)?;
+        let get_ssh_project = |ssh_user_host: &SshUserHost| -> anyhow::Result<String> {
+            let mut ssh_project = get_ssh_home(ssh_user_host)?;
 
-                ssh_home.write_fmt(format_args!("/{PROJECT_DIRECTORY}",))?;
+            ssh_project.write_fmt(format_args!(
+                "/{PROJECT_DIRECTORY}/{project_name}-{project_id}/{release}",
+                project_name = project_name.as_ref(),
+            ))?;
 
-                ssh_home
-            };
+            Ok(ssh_project)
+        };
 
-            let ssh_project = format!(
-                "{ssh_root}/{project_name}-{project_id}/{reference_name}-{commit_sha}",
-                project_name = project_name.as_ref(),
-                reference_name = reference_name.as_ref(),
-                commit_sha = commit_sha.get_short_sha(),
-            );
+        let batch_size = match (batch_size, batch_percent) {
+            (Some(batch_size), _) => Some(batch_size),
+            (None, Some(batch_percent)) => {
+                NonZeroUsize::new((ssh_user_hosts.len() * batch_percent as usize).div_ceil(100))
+            },
+            (None, None) => None,
+        };
 
-            let command_str = command.get_command_str();
+        match batch_size {
+            Some(batch_size) => {
+                let health_check = if matches!(command, Command::Up | Command::DownAndUp) {
+                    if health_check.is_none() {
+                        log::warn!(
+                            "No health check is set for the project {project_id}, so each batch \
+                             is only gated on the result of the command"
+                        );
+                    }
 
-            if command == Command::DownAndUp {
-                let mut command =
-                    create_ssh_command(ssh_user_host, format!("cat {ssh_project:?}/../last-up"));
+                    health_check.as_ref()
+                } else {
+                    None
+                };
+
+                let ssh_user_hosts: Vec<&SshUserHost> = ssh_user_hosts.iter().collect();
 
-                command.stdout(Stdio::piped());
-                command.stderr(Stdio::piped());
+                let batches: Vec<&[&SshUserHost]> =
+                    ssh_user_hosts.chunks(batch_size.get()).collect();
 
-                let output = command.execute_output()?;
+                let batch_count = batches.len();
 
-                if output.status.success() {
-                    let mut folder = String::from_utf8(output.stdout)?;
+                for (i, batch) in batches.into_iter().enumerate() {
+                    log::info!(
+                        "Rolling out batch {number}/{batch_count} ({hosts})",
+                        number = i + 1,
+                        hosts = batch.iter().map(|h| h.to_string()).collect::<Vec<_>>().join(", "),
+                    );
 
-                    folder.trim_in_place();
+                    run_on_hosts(
+                        batch.iter().copied(),
+                        NonZeroUsize::new(batch.len()).unwrap(),
+                        OnError::Continue,
+                        |ssh_user_host| {
+                            let ssh_project = get_ssh_project(ssh_user_host)?;
 
-                    log::info!("Trying to shut down {folder} first");
+                            control_back_release(
+                                ssh_user_host,
+                                ssh_project.as_str(),
+                                release.as_str(),
+                                &command,
+                                health_check,
+                                auto_rollback,
+                            )
+                        },
+                    )
+                    .map_err(|err| {
+                        anyhow!(
+                            "The rollout stopped at batch {number}/{batch_count}: {err}",
+                            number = i + 1
+                        )
+                    })?;
+                }
+            },
+            None => {
+                run_on_hosts(
+                    &ssh_user_hosts,
+                    NonZeroUsize::MIN,
+                    OnError::Abort,
+                    |ssh_user_host| {
+                        let ssh_project = get_ssh_project(ssh_user_host)?;
 
-                    {
-                        let mut command = create_ssh_command(
+                        control_back_release(
                             ssh_user_host,
-                            format!(
-                                "cd {ssh_project:?}/../{folder} && {command}",
-                                command = Command::Down.get_command_str(),
-                            ),
-                        );
+                            ssh_project.as_str(),
+                            release.as_str(),
+                            &command,
+                            health_check.as_ref(),
+                            auto_rollback,
+                        )
+                    },
+                )?;
+            },
+        }
 
-                        let output = command.execute_output()?;
+        log::info!("Successfully!");
+    }
 
-                        if !output.status.success() {
-                            log::warn!("{folder} cannot be fully shut down");
-                        }
-                    }
+    Ok(())
+}
+
+/// Run `command` for the release in `ssh_project` on the host. `release` is the name of the release
+/// directory, which is recorded in `control.log` and `last-up`. After bringing the release up, the
+/// health check (if any) has to pass before `last-up` is updated. Otherwise, the failure is
+/// recorded in `control.log` and, with `auto_rollback`, the release in `last-up` is brought back
+/// up.
+pub(crate) fn control_back_release(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+    release: &str,
+    command: &Command,
+    health_check: Option<&HealthCheck>,
+    auto_rollback: bool,
+) -> anyhow::Result<()> {
+    log::info!("Controlling to {ssh_user_host} ({command})", command = command.as_str());
+
+    let command_str = command.get_command_str();
+
+    let quoted_ssh_project = quote(ssh_project);
+
+    let is_up = matches!(command, Command::Up | Command::DownAndUp);
+
+    let previous =
+        if is_up { read_ssh_last_up(ssh_user_host, format!("{ssh_project}/.."))? } else { None };
+
+    if *command == Command::DownAndUp {
+        if let Some(folder) = previous.as_deref() {
+            let command_in_ssh = format!(
+                "cd {quoted_ssh_project}/../{quoted_folder} && {command}",
+                quoted_folder = quote(folder),
+                command = Command::Down.get_command_str(),
+            );
+
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                log::info!("Trying to shut down {folder} first");
+
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
+                    log::warn!("{folder} cannot be fully shut down");
                 }
             }
+        }
+    }
 
-            {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!(
-                        "cd {ssh_project:?} && echo \"{timestamp} {command} \
-                         {reference_name}-{commit_sha}\" >> {ssh_project:?}/../control.log && \
-                         {command_str}",
-                        reference_name = reference_name.as_ref(),
-                        timestamp = current_timestamp(),
-                        commit_sha = commit_sha.get_short_sha(),
-                        command = command.as_str(),
-                    ),
-                );
-
-                let output = command.execute_output()?;
-
-                if !output.status.success() {
-                    return Err(anyhow!("Control failed!"));
-                }
+    let result = (|| {
+        let command_in_ssh = format!(
+            "cd {quoted_ssh_project} && printf '%s\\n' {line} >> \
+             {quoted_ssh_project}/../control.log && {command_str}",
+            line = quote(&format!(
+                "{timestamp} {command} {release}",
+                timestamp = current_timestamp(),
+                command = command.as_str(),
+            )),
+        );
+
+        if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+            let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+            let status = execute_prefixed(&mut command)?;
+
+            if !status.success() {
+                return Err(anyhow!("Control failed!"));
             }
+        }
 
-            if matches!(command, Command::Up | Command::DownAndUp) {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!(
-                        "cd {ssh_project:?} && echo \"{reference_name}-{commit_sha}\" > \
-                         {ssh_project:?}/../last-up",
-                        reference_name = reference_name.as_ref(),
-                        commit_sha = commit_sha.get_short_sha(),
-                    ),
-                );
-
-                let status = command.execute()?;
-
-                if let Some(0) = status {
-                    // do nothing
-                } else {
-                    log::warn!("The latest version information cannot be written");
-                }
+        if is_up {
+            if let Some(health_check) = health_check {
+                wait_for_health_check(ssh_user_host, ssh_project, health_check)?;
             }
         }
 
-        log::info!("Successfully!");
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        if !is_up {
+            return Err(err);
+        }
+
+        append_control_log(ssh_user_host, ssh_project, FAILED_EVENT, release);
+
+        if !auto_rollback {
+            return Err(err);
+        }
+
+        return match roll_back_back_release(
+            ssh_user_host,
+            ssh_project,
+            release,
+            previous.as_deref(),
+        ) {
+            Ok(previous) => Err(anyhow!("{err} ({previous} is up again)")),
+            Err(rollback_err) => Err(anyhow!("{err} (auto rollback failed: {rollback_err})")),
+        };
+    }
+
+    if is_up {
+        let command_in_ssh = format!(
+            "cd {quoted_ssh_project} && printf '%s\\n' {quoted_release} > \
+             {quoted_ssh_project}/../last-up",
+            quoted_release = quote(release),
+        );
+
+        if plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+            return Ok(());
+        }
+
+        let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+        let status = execute_prefixed(&mut command)?;
+
+        if !status.success() {
+            log::warn!("The latest version information cannot be written");
+        }
     }
 
     Ok(())
 }
+
+/// Shut down the failed release in `ssh_project` and bring `previous` (the release in `last-up`)
+/// back up. The name of the restored release is returned.
+fn roll_back_back_release(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+    release: &str,
+    previous: Option<&str>,
+) -> anyhow::Result<String> {
+    let previous = match previous {
+        Some(previous) if previous != release => previous,
+        _ => return Err(anyhow!("no previous release is recorded in last-up")),
+    };
+
+    log::warn!("Rolling {ssh_user_host} back to {previous}");
+
+    let quoted_ssh_project = quote(ssh_project);
+
+    let command_in_ssh =
+        format!("cd {quoted_ssh_project} && {command}", command = Command::Down.get_command_str());
+
+    if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+        let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+        let status = execute_prefixed(&mut command)?;
+
+        if !status.success() {
+            log::warn!("{release} cannot be fully shut down");
+        }
+    }
+
+    let command_in_ssh = format!(
+        "cd {quoted_ssh_project}/../{quoted_previous} && printf '%s\\n' {line} >> \
+         {quoted_ssh_project}/../control.log && {command}",
+        quoted_previous = quote(previous),
+        line = quote(&format!(
+            "{timestamp} {AUTO_ROLLBACK_EVENT} {previous}",
+            timestamp = current_timestamp()
+        )),
+        command = Command::Up.get_command_str(),
+    );
+
+    if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+        let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+        let status = execute_prefixed(&mut command)?;
+
+        if !status.success() {
+            return Err(anyhow!("{previous} cannot be brought up"));
+        }
+    }
+
+    Ok(String::from(previous))
+}
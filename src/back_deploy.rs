@@ -1,13 +1,16 @@
 use std::fmt::Write as FmtWrite;
 
 use anyhow::anyhow;
-use execute::{command_args, Execute};
 use tempfile::tempdir;
 
 use crate::{
+    archive::*,
+    artifact_cache::{ArtifactCache, BackArtifacts},
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    models::OnError,
+    shell::quote,
 };
 
 pub(crate) fn back_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -22,12 +25,18 @@ pub(crate) fn back_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
         phase,
         gitlab_api_url_prefix: api_url_prefix,
         gitlab_api_token: api_token,
+        gitlab_api_ca_file: ca_file,
+        parallel,
+        zstd_level,
+        zstd_threads,
+        docker_registry,
+        artifact_cache,
+        artifact_cache_max_size,
+        artifact_cache_max_age,
+        ..
     } = cli_args.command
     {
-        check_zstd()?;
         check_ssh()?;
-        check_wget()?;
-        check_tar()?;
         check_bash()?;
         check_docker()?;
 
@@ -38,22 +47,89 @@ pub(crate) fn back_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
             return Ok(());
         }
 
+        let artifact_cache = artifact_cache.map(|directory| {
+            ArtifactCache::new(directory, artifact_cache_max_size, artifact_cache_max_age)
+        });
+
+        let cached_artifacts = match artifact_cache.as_ref() {
+            Some(artifact_cache) => artifact_cache.get_back_artifacts(
+                project_id,
+                &commit_sha,
+                build_target.as_ref(),
+                docker_registry.as_ref(),
+            )?,
+            None => None,
+        };
+
         let temp_dir = tempdir()?;
 
-        download_and_extract_archive(
-            &temp_dir,
-            api_url_prefix,
-            api_token,
-            project_id,
-            &commit_sha,
-        )?;
+        // the images in a cache entry for a registry have been pushed already
+        let BackArtifacts {
+            directory: deploy_dir,
+            image_names,
+            docker_compose,
+        } = match cached_artifacts {
+            Some(cached_artifacts) => cached_artifacts,
+            None => {
+                download_and_extract_archive(
+                    &temp_dir,
+                    api_url_prefix,
+                    api_token,
+                    ca_file.as_deref(),
+                    project_id,
+                    &commit_sha,
+                )?;
+
+                let (image_names, docker_compose) = check_back_deploy(
+                    &temp_dir,
+                    &commit_sha,
+                    build_target.as_ref(),
+                    docker_registry.as_ref(),
+                )?;
+
+                run_back_build(&temp_dir, &commit_sha, build_target.as_ref())?;
+
+                let deploy_dir = temp_dir.path().join("deploy");
+
+                for image_name in image_names.iter() {
+                    if let Some(docker_registry) = docker_registry.as_ref() {
+                        push_back_image(
+                            deploy_dir.as_path(),
+                            image_name,
+                            &commit_sha,
+                            docker_registry,
+                        )?;
+                    } else if !is_dry_run() {
+                        ensure_zstd_tarball(
+                            deploy_dir.as_path(),
+                            image_name.as_ref(),
+                            zstd_level,
+                            zstd_threads,
+                        )?;
+                    }
+                }
 
-        let (image_name, docker_compose) =
-            check_back_deploy(&temp_dir, &commit_sha, build_target.as_ref())?;
+                let back_artifacts = BackArtifacts {
+                    directory: deploy_dir,
+                    image_names,
+                    docker_compose,
+                };
+
+                if let Some(artifact_cache) = artifact_cache.as_ref() {
+                    artifact_cache.put_back_artifacts(
+                        project_id,
+                        &commit_sha,
+                        build_target.as_ref(),
+                        docker_registry.as_ref(),
+                        &back_artifacts,
+                    )?;
+                }
 
-        run_back_build(&temp_dir, &commit_sha, build_target.as_ref())?;
+                back_artifacts
+            },
+        };
 
-        for ssh_user_host in ssh_user_hosts.iter() {
+        run_on_hosts(&ssh_user_hosts, parallel, OnError::Continue, |ssh_user_host| {
             log::info!("Deploying to {ssh_user_host}");
 
             let ssh_root = {
@@ -71,15 +147,15 @@ pub(crate) fn back_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
                 commit_sha = commit_sha.get_short_sha(),
             );
 
-            {
-                let mut command =
-                    create_ssh_command(ssh_user_host, format!("mkdir -p {ssh_project:?}"));
+            let command_in_ssh =
+                format!("mkdir -p {ssh_project}", ssh_project = quote(&ssh_project));
 
-                let status = command.execute()?;
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
                     return Err(anyhow!(
                         "Cannot create the directory {ssh_project:?} for storing the archive of \
                          public static files."
@@ -89,68 +165,89 @@ pub(crate) fn back_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
 
             let ssh_docker_compose_path = format!("{ssh_project}/docker-compose.yml");
 
+            if !plan_write(ssh_user_host, ssh_docker_compose_path.as_str(), docker_compose.as_str())
             {
-                let mut command =
-                    create_ssh_command(ssh_user_host, format!("cat - > {ssh_docker_compose_path}"));
-
-                let status = command.execute_input(docker_compose.as_str())?;
-
-                if let Some(0) = status {
-                    // do nothing
-                } else {
-                    return Err(anyhow!(
-                        "Cannot create the docker compose file {ssh_docker_compose_path:?}."
-                    ));
-                }
-            }
-
-            let tarball_path =
-                format!("deploy/{image_name}.tar.zst", image_name = image_name.as_ref());
-
-            let ssh_tarball_path =
-                format!("{ssh_project}/{image_name}.tar.zst", image_name = image_name.as_ref());
-
-            {
-                let mut command = create_scp_command(
+                let mut command = create_ssh_command(
                     ssh_user_host,
-                    tarball_path.as_str(),
-                    ssh_tarball_path.as_str(),
+                    format!(
+                        "cat - > {ssh_docker_compose_path}",
+                        ssh_docker_compose_path = quote(&ssh_docker_compose_path)
+                    ),
                 );
 
-                command.current_dir(temp_dir.path());
-
-                let status = command.execute()?;
+                let status =
+                    execute_prefixed_input_reader(&mut command, &mut docker_compose.as_bytes())?;
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                if !status.success() {
                     return Err(anyhow!(
-                        "Cannot copy {tarball_path:?} to {ssh_user_host}:{ssh_tarball_path:?} \
-                         ({ssh_user_host_port}).",
-                        ssh_user_host = ssh_user_host.user_host(),
-                        ssh_user_host_port = ssh_user_host.get_port(),
+                        "Cannot create the docker compose file {ssh_docker_compose_path:?}."
                     ));
                 }
             }
 
-            log::info!("Extracting {tarball_path}");
-
-            {
-                let mut command1 = command_args!("zstd", "-d", "-c", "-f", tarball_path);
+            for image_name in image_names.iter() {
+                if let Some(docker_registry) = docker_registry.as_ref() {
+                    let image_tag = get_image_tag(image_name, &commit_sha, Some(docker_registry));
 
-                command1.current_dir(temp_dir.path());
+                    let command_in_ssh =
+                        format!("docker image pull {image_tag}", image_tag = quote(&image_tag));
 
-                let mut command2 = create_ssh_command(ssh_user_host, "docker image load");
+                    if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                        let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                let status = command1.execute_multiple(&mut [&mut command2])?;
+                        let status = execute_prefixed(&mut command)?;
 
-                if let Some(0) = status {
-                    // do nothing
+                        if !status.success() {
+                            return Err(anyhow!("Cannot pull the docker image {image_tag}"));
+                        }
+                    }
                 } else {
-                    return Err(anyhow!("Cannot deploy the docker image"));
+                    let tarball_path = deploy_dir
+                        .join(format!("{image_name}.tar.zst", image_name = image_name.as_ref()))
+                        .to_string_lossy()
+                        .into_owned();
+
+                    let ssh_tarball_path = format!(
+                        "{ssh_project}/{image_name}.tar.zst",
+                        image_name = image_name.as_ref()
+                    );
+
+                    if !plan_upload(ssh_user_host, tarball_path.as_str(), ssh_tarball_path.as_str())
+                    {
+                        let mut command = create_scp_command(
+                            ssh_user_host,
+                            tarball_path.as_str(),
+                            ssh_tarball_path.as_str(),
+                        );
+
+                        let status = execute_prefixed(&mut command)?;
+
+                        if !status.success() {
+                            return Err(anyhow!(
+                                "Cannot copy {tarball_path:?} to {ssh_tarball_path:?} on \
+                                 {ssh_user_host}."
+                            ));
+                        }
+                    }
+
+                    if !plan_ssh(ssh_user_host, format!("docker image load < {tarball_path}")) {
+                        log::info!("Extracting {tarball_path}");
+
+                        let mut reader = open_tarball(deploy_dir.as_path(), image_name.as_ref())?;
+
+                        let mut command = create_ssh_command(ssh_user_host, "docker image load");
+
+                        let status = execute_prefixed_input_reader(&mut command, &mut reader)?;
+
+                        if !status.success() {
+                            return Err(anyhow!("Cannot deploy the docker image"));
+                        }
+                    }
                 }
             }
-        }
+
+            Ok(())
+        })?;
 
         log::info!("Successfully!");
     }
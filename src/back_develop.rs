@@ -7,6 +7,7 @@ use crate::{
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    shell::quote,
 };
 
 pub(crate) fn back_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -46,32 +47,33 @@ pub(crate) fn back_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
 
             check_back_deploy_via_ssh(&ssh_user_host, ssh_root.as_str())?;
 
-            log::info!("Running deploy/develop-down.sh");
+            let command_in_ssh = format!(
+                "cd {ssh_root} && bash deploy/develop-down.sh",
+                ssh_root = quote(&ssh_root)
+            );
 
-            {
-                let mut command = create_ssh_command(
-                    &ssh_user_host,
-                    format!("cd {ssh_root:?} && bash 'deploy/develop-down.sh'",),
-                );
+            if !plan_ssh(&ssh_user_host, command_in_ssh.as_str()) {
+                log::info!("Running deploy/develop-down.sh");
+
+                let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
 
                 command.execute_output()?;
             }
 
-            log::info!(
-                "Trying to checkout {reference:?} and pull the branch",
-                reference = reference.as_ref()
+            let command_in_ssh = format!(
+                "cd {ssh_root} && git checkout {reference} && git pull origin {reference}",
+                ssh_root = quote(&ssh_root),
+                reference = quote(reference.as_ref()),
             );
 
-            {
-                let mut command = create_ssh_command(
-                    &ssh_user_host,
-                    format!(
-                        "cd {ssh_root:?} && git checkout {reference:?} && git pull origin \
-                         {reference:?}",
-                        reference = reference.as_ref(),
-                    ),
+            if !plan_ssh(&ssh_user_host, command_in_ssh.as_str()) {
+                log::info!(
+                    "Trying to checkout {reference:?} and pull the branch",
+                    reference = reference.as_ref()
                 );
 
+                let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
+
                 let output = command.execute_output()?;
 
                 if !output.status.success() {
@@ -94,39 +96,43 @@ pub(crate) fn back_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
                 reference = reference.as_ref(),
             );
 
-            let mut command = create_ssh_command(
-                &ssh_user_host,
-                format!(
-                    "mkdir -p {ssh_root:?} && cd {ssh_root:?} && git clone --recursive \
-                     {ssh_url:?} . && git checkout {reference:?}",
-                    ssh_root = ssh_root,
-                    reference = reference.as_ref(),
-                ),
+            let command_in_ssh = format!(
+                "mkdir -p {ssh_root} && cd {ssh_root} && git clone --recursive {ssh_url} . && git \
+                 checkout {reference}",
+                ssh_root = quote(&ssh_root),
+                ssh_url = quote(&ssh_url),
+                reference = quote(reference.as_ref()),
             );
 
-            let output = command.execute_output()?;
+            if !plan_ssh(&ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
 
-            if !output.status.success() {
-                return Err(anyhow!(
-                    "Cannot clone {ssh_url:?} and checkout out {reference:?}",
-                    reference = reference.as_ref()
-                ));
+                let output = command.execute_output()?;
+
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "Cannot clone {ssh_url:?} and checkout out {reference:?}",
+                        reference = reference.as_ref()
+                    ));
+                }
             }
         }
 
-        check_back_deploy_via_ssh(&ssh_user_host, ssh_root.as_str())?;
+        let command_in_ssh =
+            format!("cd {ssh_root} && bash deploy/develop-up.sh", ssh_root = quote(&ssh_root));
 
-        log::info!("Running deploy/develop-up.sh");
+        if !plan_ssh(&ssh_user_host, command_in_ssh.as_str()) {
+            check_back_deploy_via_ssh(&ssh_user_host, ssh_root.as_str())?;
+
+            log::info!("Running deploy/develop-up.sh");
 
-        let mut command = create_ssh_command(
-            &ssh_user_host,
-            format!("cd {SSH_ROOT:?} && bash 'deploy/develop-up.sh'", SSH_ROOT = ssh_root),
-        );
+            let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
 
-        let output = command.execute_output()?;
+            let output = command.execute_output()?;
 
-        if !output.status.success() {
-            return Err(anyhow!("Failed!"));
+            if !output.status.success() {
+                return Err(anyhow!("Failed!"));
+            }
         }
 
         log::info!("Successfully!");
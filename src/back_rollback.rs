@@ -0,0 +1,91 @@
+use std::num::NonZeroUsize;
+
+use anyhow::anyhow;
+
+use crate::{
+    back_control::control_back_release,
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    functions::*,
+    inventory::ProjectInventory,
+    models::*,
+};
+
+pub(crate) fn back_rollback(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::BackendRollback { .. }));
+
+    if let CLICommands::BackendRollback {
+        gitlab_project_id: project_id,
+        project_name,
+        phase,
+        to,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        let ProjectInventory {
+            ssh_user_hosts,
+            health_check,
+        } = find_project_inventory(phase, project_id)?;
+
+        if ssh_user_hosts.is_empty() {
+            log::warn!("No hosts to roll back!");
+            return Ok(());
+        }
+
+        run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Abort, |ssh_user_host| {
+            log::info!("Rolling back {ssh_user_host}");
+
+            let ssh_project_root = format!(
+                "{ssh_home}/{PROJECT_DIRECTORY}/{project_name}-{project_id}",
+                ssh_home = get_ssh_home(ssh_user_host)?,
+                project_name = project_name.as_ref(),
+            );
+
+            let releases = list_ssh_releases(ssh_user_host, ssh_project_root.as_str())?;
+            let history = read_ssh_control_log(ssh_user_host, ssh_project_root.as_str())?;
+            let current = read_ssh_last_up(ssh_user_host, ssh_project_root.as_str())?;
+
+            let release = find_rollback_release(
+                &releases,
+                &history,
+                &[Command::Up.as_str(), Command::DownAndUp.as_str(), AUTO_ROLLBACK_EVENT],
+                current.as_deref(),
+                to.as_ref(),
+            )
+            .map_err(|err| anyhow!("{err} on {ssh_user_host}"))?;
+
+            if current.as_deref() == Some(release.as_str()) {
+                log::info!("{release} is already up on {ssh_user_host}");
+                return Ok(());
+            }
+
+            log::info!(
+                "Rolling back from {current} to {release}",
+                current = current.as_deref().unwrap_or("(none)")
+            );
+
+            let ssh_project = format!("{ssh_project_root}/{release}");
+
+            control_back_release(
+                ssh_user_host,
+                ssh_project.as_str(),
+                release.as_str(),
+                &Command::DownAndUp,
+                health_check.as_ref(),
+                false,
+            )?;
+
+            if let Some(current) = current.as_deref() {
+                append_control_log(ssh_user_host, ssh_project.as_str(), ROLLED_BACK_EVENT, current);
+            }
+
+            Ok(())
+        })?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
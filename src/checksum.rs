@@ -0,0 +1,110 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+
+/// Compute the SHA-256 checksum of a file as lowercase hex.
+pub(crate) fn compute_sha256<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Compute the checksums of the files in `directory`, in the format of `sha256sum`.
+pub(crate) fn compute_sha256sums<S: AsRef<str>>(
+    directory: &Path,
+    file_names: &[S],
+) -> io::Result<String> {
+    let mut sha256sums = String::new();
+
+    for file_name in file_names {
+        let file_name = file_name.as_ref();
+
+        sha256sums.push_str(compute_sha256(directory.join(file_name))?.as_str());
+        sha256sums.push_str("  ");
+        sha256sums.push_str(file_name);
+        sha256sums.push('\n');
+    }
+
+    Ok(sha256sums)
+}
+
+/// Check the files in `directory` against checksums in the format of `sha256sum`, like
+/// `sha256sum -c`. A missing file or a line which is not a checksum counts as a mismatch, and so do
+/// checksums without any lines.
+pub(crate) fn check_sha256sums(directory: &Path, sha256sums: &str) -> io::Result<bool> {
+    let mut is_empty = true;
+
+    for line in sha256sums.lines() {
+        // <checksum>  <file> (text mode) or <checksum> *<file> (binary mode)
+        let (checksum, file_name) = match line.split_once("  ").or_else(|| line.split_once(" *")) {
+            Some(pair) => pair,
+            None => return Ok(false),
+        };
+
+        match compute_sha256(directory.join(file_name)) {
+            Ok(actual) => {
+                if !actual.eq_ignore_ascii_case(checksum) {
+                    return Ok(false);
+                }
+            },
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        is_empty = false;
+    }
+
+    Ok(!is_empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn compute_and_check() {
+        let dir = tempdir().unwrap();
+
+        fs::write(dir.path().join("abc"), "abc").unwrap();
+        fs::write(dir.path().join("a b"), "").unwrap();
+
+        let sha256sums = compute_sha256sums(dir.path(), &["abc", "a b"]).unwrap();
+
+        assert_eq!(format!("{ABC_SHA256}  abc\n{EMPTY_SHA256}  a b\n"), sha256sums);
+        assert!(check_sha256sums(dir.path(), sha256sums.as_str()).unwrap());
+
+        // the binary mode and uppercase hex of `sha256sum`
+        let binary = format!("{} *abc\n", ABC_SHA256.to_uppercase());
+
+        assert!(check_sha256sums(dir.path(), binary.as_str()).unwrap());
+    }
+
+    #[test]
+    fn check_mismatch() {
+        let dir = tempdir().unwrap();
+
+        fs::write(dir.path().join("abc"), "abd").unwrap();
+
+        let mismatched = format!("{ABC_SHA256}  abc\n");
+        let missing = format!("{ABC_SHA256}  missing\n");
+
+        assert!(!check_sha256sums(dir.path(), mismatched.as_str()).unwrap());
+        assert!(!check_sha256sums(dir.path(), missing.as_str()).unwrap());
+        assert!(!check_sha256sums(dir.path(), "not a checksum\n").unwrap());
+        assert!(!check_sha256sums(dir.path(), "").unwrap());
+    }
+}
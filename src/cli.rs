@@ -1,5 +1,7 @@
+use std::{num::NonZeroUsize, path::PathBuf};
+
 use anyhow::anyhow;
-use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use concat_with::concat_line;
 use terminal_size::terminal_size;
 use validators::{
@@ -19,14 +21,21 @@ const APP_ABOUT: &str = concat!(
     "GitLab Deploy is used for deploying software projects to multiple hosts during different \
      phases\n\nEXAMPLES:\n",
     concat_line!(prefix "gitlab-deploy ",
-        "frontend-develop --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --build-target develop",
-        "frontend-deploy  --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test --build-target test",
-        "frontend-control --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
-        "backend-develop  --gitlab-project-id 123 --gitlab-project-path website-api                     --project-name website --reference develop",
-        "backend-deploy   --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
-        "backend-control  --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test --command up",
-        "simple-deploy    --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
-        "simple-control   --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test sudo /usr/local/bin/apply-nginx.sh dev.env",
+        "frontend-develop  --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --build-target develop",
+        "frontend-deploy   --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test --build-target test",
+        "frontend-control  --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
+        "frontend-rollback --gitlab-project-id 123 --project-name website --phase test --to 0b14cd4f",
+        "backend-develop   --gitlab-project-id 123 --gitlab-project-path website-api                     --project-name website --reference develop",
+        "backend-deploy    --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
+        "backend-control   --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test --command up",
+        "backend-rollback  --gitlab-project-id 123 --project-name website --phase test",
+        "simple-deploy     --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test",
+        "simple-control    --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --phase test sudo /usr/local/bin/apply-nginx.sh dev.env",
+        "promote           --gitlab-project-id 123 --commit-sha 0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f --project-name website --reference-name pre-release --from test --to production",
+        "prune             --gitlab-project-id 123 --project-name website --phase test --keep 5",
+        "status            --gitlab-project-id 123 --project-name website --phase test --format json",
+        "verify            --gitlab-project-id 123 --project-name website --phase test",
+        "hosts             trust test",
     )
 );
 
@@ -37,10 +46,42 @@ const APP_ABOUT: &str = concat!(
 #[command(author = CARGO_PKG_AUTHORS)]
 #[command(after_help = AFTER_HELP)]
 pub struct CLIArgs {
+    #[arg(long, global = true)]
+    #[arg(help = "Print the plan of remote commands, uploads and file writes for every host \
+                  instead of changing anything")]
+    pub dry_run: bool,
     #[command(subcommand)]
     pub command: CLICommands,
 }
 
+/// The options of the subcommands which run on the hosts of a phase one by one (or in parallel).
+#[derive(Debug, Args)]
+pub struct HostArgs {
+    #[arg(long)]
+    #[arg(value_parser = parse_on_error)]
+    #[arg(help = "Set what to do with the remaining hosts after a host fails, `continue` or \
+                  `abort` [default: `continue` for deployments and `abort` for the others]")]
+    pub on_error:    Option<OnError>,
+    #[command(flatten)]
+    pub host_filter: HostFilterArgs,
+}
+
+/// The options of the subcommands which handle the hosts of a phase.
+#[derive(Debug, Clone, Args)]
+pub struct HostFilterArgs {
+    #[arg(long, value_name = "PATTERN")]
+    #[arg(value_parser = parse_host_pattern)]
+    #[arg(help = "Only handle the hosts of the phase which match this pattern (a host, \
+                  user@host or label, where `*` and `?` are wildcards). It can be given more \
+                  than once")]
+    pub limit:   Vec<HostPattern>,
+    #[arg(long, value_name = "PATTERN")]
+    #[arg(value_parser = parse_host_pattern)]
+    #[arg(help = "Skip the hosts of the phase which match this pattern (a host, user@host or \
+                  label, where `*` and `?` are wildcards). It can be given more than once")]
+    pub exclude: Vec<HostPattern>,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum CLICommands {
     #[command(about = "Fetch the project via GitLab API and then build it and use the public \
@@ -66,9 +107,15 @@ pub enum CLICommands {
         #[arg(value_parser = parse_api_token)]
         #[arg(help = "Set the token of GitLab APIs")]
         gitlab_api_token:      ApiToken,
+        #[arg(long, visible_aliases = ["api-ca-file"], env = "GITLAB_API_CA_FILE")]
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        #[arg(help = "Set the PEM file of the CA certificates to trust for GitLab APIs instead \
+                      of the built-in ones")]
+        gitlab_api_ca_file:    Option<PathBuf>,
         #[arg(long, visible_aliases = ["ssh-user-host"], env = "DEVELOP_SSH_HOST")]
         #[arg(value_parser = parse_ssh_user_host)]
-        #[arg(help = "Set the SSH user, host and the optional port for development")]
+        #[arg(help = "Set the SSH host for development, as [user@]host[:port] with an IPv6 host \
+                      in brackets, or an alias in ~/.ssh/config")]
         develop_ssh_user_host: SshUserHost,
     },
     #[command(about = "Fetch the project via GitLab API and then build it and deploy the \
@@ -77,35 +124,67 @@ pub enum CLICommands {
     FrontendDeploy {
         #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
         #[arg(help = "Set the ID on GitLab of this project")]
-        gitlab_project_id:     u64,
+        gitlab_project_id:       u64,
         #[arg(long, visible_aliases = ["sha"], env = "CI_COMMIT_SHA")]
         #[arg(value_parser = parse_commit_sha)]
         #[arg(help = "Set the sha of the commit")]
-        commit_sha:            CommitSha,
+        commit_sha:              CommitSha,
         #[arg(long, env = "CI_PROJECT_NAME")]
         #[arg(value_parser = parse_name)]
         #[arg(help = "Set the name of this project")]
-        project_name:          Name,
+        project_name:            Name,
         #[arg(long, env = "CI_COMMIT_REF_NAME")]
         #[arg(value_parser = parse_name)]
         #[arg(help = "Set the reference name of the commit")]
-        reference_name:        Name,
+        reference_name:          Name,
         #[arg(long, visible_aliases = ["target"])]
         #[arg(value_parser = parse_build_target)]
         #[arg(help = "Set the target of this build")]
-        build_target:          BuildTarget,
+        build_target:            BuildTarget,
         #[arg(long, visible_aliases = ["phase"])]
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
-        phase:                 Phase,
+        phase:                   Phase,
+        #[command(flatten)]
+        host_args:               HostArgs,
         #[arg(long, visible_aliases = ["api-url-prefix"], env = "GITLAB_API_URL_PREFIX")]
         #[arg(value_parser = parse_api_url_prefix)]
         #[arg(help = "Set the URL prefix for GitLab APIs")]
-        gitlab_api_url_prefix: ApiUrlPrefix,
+        gitlab_api_url_prefix:   ApiUrlPrefix,
         #[arg(long, visible_aliases = ["api-token"], env = "GITLAB_API_TOKEN")]
         #[arg(value_parser = parse_api_token)]
         #[arg(help = "Set the token of GitLab APIs")]
-        gitlab_api_token:      ApiToken,
+        gitlab_api_token:        ApiToken,
+        #[arg(long, visible_aliases = ["api-ca-file"], env = "GITLAB_API_CA_FILE")]
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        #[arg(help = "Set the PEM file of the CA certificates to trust for GitLab APIs instead \
+                      of the built-in ones")]
+        gitlab_api_ca_file:      Option<PathBuf>,
+        #[arg(long, default_value = "1")]
+        #[arg(help = "Set the maximum number of hosts to deploy to at once")]
+        parallel:                NonZeroUsize,
+        #[arg(long, default_value = "3")]
+        #[arg(value_parser = clap::value_parser!(i32).range(1..=22))]
+        #[arg(help = "Set the zstd compression level used when the build only produces a .tar \
+                      archive")]
+        zstd_level:              i32,
+        #[arg(long, default_value = "0")]
+        #[arg(help = "Set the number of threads used for zstd compression (0 means one thread \
+                      per CPU)")]
+        zstd_threads:            u32,
+        #[arg(long, env = "GITLAB_DEPLOY_ARTIFACT_CACHE")]
+        #[arg(value_hint = clap::ValueHint::DirPath)]
+        #[arg(help = "Set the directory to cache the built artifacts in, so that deploying the \
+                      same commit and build target again does not download and build it again")]
+        artifact_cache:          Option<PathBuf>,
+        #[arg(long, default_value = "10240")]
+        #[arg(help = "Set the maximum size of the artifact cache in MiB. The least recently \
+                      used artifacts are evicted first")]
+        artifact_cache_max_size: u64,
+        #[arg(long, default_value = "30")]
+        #[arg(help = "Set the number of days after which unused artifacts are evicted from the \
+                      artifact cache")]
+        artifact_cache_max_age:  u64,
     },
     #[command(about = "Control the project on multiple hosts according to the phase")]
     #[command(after_help = AFTER_HELP)]
@@ -129,6 +208,31 @@ pub enum CLICommands {
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
         phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
+    },
+    #[command(about = "Roll the project back to the previous release (or the release of a \
+                       specific commit) on multiple hosts according to the phase")]
+    #[command(after_help = AFTER_HELP)]
+    FrontendRollback {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id: u64,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:      Name,
+        #[arg(long, visible_aliases = ["phase"])]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase")]
+        phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
+        #[arg(long)]
+        #[arg(value_parser = parse_commit_sha_prefix)]
+        #[arg(help = "Set the sha (or at least its first 8 characters) of the commit to roll \
+                      back to instead of the previous release")]
+        to:                Option<CommitShaPrefix>,
     },
     #[command(about = "Fetch the project via Git and checkout to a specific branch and then \
                        start up the service on a development host")]
@@ -155,7 +259,8 @@ pub enum CLICommands {
         gitlab_ssh_url_prefix: SshUrlPrefix,
         #[arg(long, visible_aliases = ["ssh-user-host"], env = "DEVELOP_SSH_HOST")]
         #[arg(value_parser = parse_ssh_user_host)]
-        #[arg(help = "Set the SSH user, host and the optional port for development")]
+        #[arg(help = "Set the SSH host for development, as [user@]host[:port] with an IPv6 host \
+                      in brackets, or an alias in ~/.ssh/config")]
         develop_ssh_user_host: SshUserHost,
     },
     #[command(about = "Fetch the project via GitLab API and then build it and deploy the docker \
@@ -164,35 +269,73 @@ pub enum CLICommands {
     BackendDeploy {
         #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
         #[arg(help = "Set the ID on GitLab of this project")]
-        gitlab_project_id:     u64,
+        gitlab_project_id:       u64,
         #[arg(long, visible_aliases = ["sha"], env = "CI_COMMIT_SHA")]
         #[arg(value_parser = parse_commit_sha)]
         #[arg(help = "Set the sha of the commit")]
-        commit_sha:            CommitSha,
+        commit_sha:              CommitSha,
         #[arg(long, env = "CI_PROJECT_NAME")]
         #[arg(value_parser = parse_name)]
         #[arg(help = "Set the name of this project")]
-        project_name:          Name,
+        project_name:            Name,
         #[arg(long, env = "CI_COMMIT_REF_NAME")]
         #[arg(value_parser = parse_name)]
         #[arg(help = "Set the reference name of the commit")]
-        reference_name:        Name,
+        reference_name:          Name,
         #[arg(long, visible_aliases = ["target"])]
         #[arg(value_parser = parse_build_target)]
         #[arg(help = "Set the target of this build")]
-        build_target:          Option<BuildTarget>,
+        build_target:            Option<BuildTarget>,
         #[arg(long, visible_aliases = ["phase"])]
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
-        phase:                 Phase,
+        phase:                   Phase,
+        #[command(flatten)]
+        host_args:               HostArgs,
         #[arg(long, visible_aliases = ["api-url-prefix"], env = "GITLAB_API_URL_PREFIX")]
         #[arg(value_parser = parse_api_url_prefix)]
         #[arg(help = "Set the URL prefix for GitLab APIs")]
-        gitlab_api_url_prefix: ApiUrlPrefix,
+        gitlab_api_url_prefix:   ApiUrlPrefix,
         #[arg(long, visible_aliases = ["api-token"], env = "GITLAB_API_TOKEN")]
         #[arg(value_parser = parse_api_token)]
         #[arg(help = "Set the token of GitLab APIs")]
-        gitlab_api_token:      ApiToken,
+        gitlab_api_token:        ApiToken,
+        #[arg(long, visible_aliases = ["api-ca-file"], env = "GITLAB_API_CA_FILE")]
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        #[arg(help = "Set the PEM file of the CA certificates to trust for GitLab APIs instead \
+                      of the built-in ones")]
+        gitlab_api_ca_file:      Option<PathBuf>,
+        #[arg(long, default_value = "1")]
+        #[arg(help = "Set the maximum number of hosts to deploy to at once")]
+        parallel:                NonZeroUsize,
+        #[arg(long, default_value = "3")]
+        #[arg(value_parser = clap::value_parser!(i32).range(1..=22))]
+        #[arg(help = "Set the zstd compression level used when the build only produces a .tar \
+                      archive")]
+        zstd_level:              i32,
+        #[arg(long, default_value = "0")]
+        #[arg(help = "Set the number of threads used for zstd compression (0 means one thread \
+                      per CPU)")]
+        zstd_threads:            u32,
+        #[arg(long, visible_aliases = ["registry"], env = "DEPLOY_DOCKER_REGISTRY")]
+        #[arg(value_parser = parse_docker_registry)]
+        #[arg(help = "Push the image to this docker registry (e.g. \
+                      registry.example.com:5000/team) once and let the hosts pull it, instead \
+                      of copying the image to every host")]
+        docker_registry:         Option<DockerRegistry>,
+        #[arg(long, env = "GITLAB_DEPLOY_ARTIFACT_CACHE")]
+        #[arg(value_hint = clap::ValueHint::DirPath)]
+        #[arg(help = "Set the directory to cache the built artifacts in, so that deploying the \
+                      same commit and build target again does not download and build it again")]
+        artifact_cache:          Option<PathBuf>,
+        #[arg(long, default_value = "10240")]
+        #[arg(help = "Set the maximum size of the artifact cache in MiB. The least recently \
+                      used artifacts are evicted first")]
+        artifact_cache_max_size: u64,
+        #[arg(long, default_value = "30")]
+        #[arg(help = "Set the number of days after which unused artifacts are evicted from the \
+                      artifact cache")]
+        artifact_cache_max_age:  u64,
     },
     #[command(about = "Control the project on multiple hosts according to the phase")]
     #[command(after_help = AFTER_HELP)]
@@ -216,10 +359,48 @@ pub enum CLICommands {
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
         phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
         #[arg(long)]
         #[arg(value_parser = parse_command)]
         #[arg(help = "Set the command")]
         command:           Command,
+        #[arg(long, conflicts_with = "batch_percent")]
+        #[arg(help = "Roll out to this number of hosts at a time and wait for the health check \
+                      of each batch before moving on")]
+        batch_size:        Option<NonZeroUsize>,
+        #[arg(long)]
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=100))]
+        #[arg(help = "Roll out to this percentage of hosts at a time and wait for the health \
+                      check of each batch before moving on")]
+        batch_percent:     Option<u8>,
+        #[arg(long)]
+        #[arg(help = "Bring the release in last-up back up on a host if the new release fails \
+                      to come up or to pass the health check there")]
+        auto_rollback:     bool,
+    },
+    #[command(about = "Roll the project back to the previous release (or the release of a \
+                       specific commit) on multiple hosts according to the phase")]
+    #[command(after_help = AFTER_HELP)]
+    BackendRollback {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id: u64,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:      Name,
+        #[arg(long, visible_aliases = ["phase"])]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase")]
+        phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
+        #[arg(long)]
+        #[arg(value_parser = parse_commit_sha_prefix)]
+        #[arg(help = "Set the sha (or at least its first 8 characters) of the commit to roll \
+                      back to instead of the previous release")]
+        to:                Option<CommitShaPrefix>,
     },
     #[command(about = "Fetch the project via GitLab API and deploy the project files on \
                        multiple hosts according to the phase")]
@@ -244,6 +425,8 @@ pub enum CLICommands {
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
         phase:                 Phase,
+        #[command(flatten)]
+        host_args:             HostArgs,
         #[arg(long, visible_aliases = ["api-url-prefix"], env = "GITLAB_API_URL_PREFIX")]
         #[arg(value_parser = parse_api_url_prefix)]
         #[arg(help = "Set the URL prefix for GitLab APIs")]
@@ -252,6 +435,14 @@ pub enum CLICommands {
         #[arg(value_parser = parse_api_token)]
         #[arg(help = "Set the token of GitLab APIs")]
         gitlab_api_token:      ApiToken,
+        #[arg(long, visible_aliases = ["api-ca-file"], env = "GITLAB_API_CA_FILE")]
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        #[arg(help = "Set the PEM file of the CA certificates to trust for GitLab APIs instead \
+                      of the built-in ones")]
+        gitlab_api_ca_file:    Option<PathBuf>,
+        #[arg(long, default_value = "1")]
+        #[arg(help = "Set the maximum number of hosts to deploy to at once")]
+        parallel:              NonZeroUsize,
     },
     #[command(about = "Control the project on multiple hosts according to the phase")]
     #[command(after_help = AFTER_HELP)]
@@ -275,6 +466,8 @@ pub enum CLICommands {
         #[arg(value_parser = parse_phase)]
         #[arg(help = "Set the phase")]
         phase:                    Phase,
+        #[command(flatten)]
+        host_args:                HostArgs,
         #[arg(long, visible_aliases = ["api-url-prefix"], env = "GITLAB_API_URL_PREFIX")]
         #[arg(value_parser = parse_api_url_prefix)]
         #[arg(help = "Set the URL prefix for GitLab APIs")]
@@ -292,6 +485,204 @@ pub enum CLICommands {
         #[arg(help = "Command to execute")]
         command:                  Vec<String>,
     },
+    #[command(about = "Copy a deployed release of the project from the hosts of one phase to \
+                       the hosts of another phase without building it again")]
+    #[command(after_help = AFTER_HELP)]
+    Promote {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id:       u64,
+        #[arg(long, visible_aliases = ["sha"], env = "CI_COMMIT_SHA")]
+        #[arg(value_parser = parse_commit_sha)]
+        #[arg(help = "Set the sha of the commit")]
+        commit_sha:              CommitSha,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:            Name,
+        #[arg(long, env = "CI_COMMIT_REF_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the reference name of the commit")]
+        reference_name:          Name,
+        #[arg(long)]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase whose hosts have the release")]
+        from:                    Phase,
+        #[arg(long)]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase to deploy the release to")]
+        to:                      Phase,
+        #[command(flatten)]
+        host_args:               HostArgs,
+        #[arg(long, default_value = "1")]
+        #[arg(help = "Set the maximum number of hosts to deploy to at once")]
+        parallel:                NonZeroUsize,
+        #[arg(long, env = "GITLAB_DEPLOY_ARTIFACT_CACHE")]
+        #[arg(value_hint = clap::ValueHint::DirPath)]
+        #[arg(help = "Set the directory of the artifact cache to take the release from before \
+                      looking for it on the hosts of the source phase")]
+        artifact_cache:          Option<PathBuf>,
+        #[arg(long, visible_aliases = ["target"])]
+        #[arg(value_parser = parse_build_target)]
+        #[arg(help = "Set the target of the build to take from the artifact cache")]
+        build_target:            Option<BuildTarget>,
+        #[arg(long, default_value = "10240")]
+        #[arg(help = "Set the maximum size of the artifact cache in MiB. The least recently \
+                      used artifacts are evicted first")]
+        artifact_cache_max_size: u64,
+        #[arg(long, default_value = "30")]
+        #[arg(help = "Set the number of days after which unused artifacts are evicted from the \
+                      artifact cache")]
+        artifact_cache_max_age:  u64,
+    },
+    #[command(about = "Remove the old releases of the project and their docker images on \
+                       multiple hosts according to the phase")]
+    #[command(after_help = AFTER_HELP)]
+    Prune {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id: u64,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:      Name,
+        #[arg(long, visible_aliases = ["phase"])]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase")]
+        phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
+        #[arg(long)]
+        #[arg(help = "Set the number of the most recent releases to keep")]
+        keep:              usize,
+    },
+    #[command(about = "Show the active release, the releases and the state of the containers on \
+                       every host of the project according to the phase")]
+    #[command(after_help = AFTER_HELP)]
+    Status {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id: u64,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:      Name,
+        #[arg(long, visible_aliases = ["phase"])]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase")]
+        phase:             Phase,
+        #[command(flatten)]
+        host_filter:       HostFilterArgs,
+        #[arg(long, default_value = "table")]
+        #[arg(value_parser = parse_output_format)]
+        #[arg(help = "Set the output format, `table` or `json`")]
+        format:            OutputFormat,
+    },
+    #[command(about = "Check that every host of the project according to the phase runs the \
+                       same release, docker images and public static files")]
+    #[command(after_help = AFTER_HELP)]
+    Verify {
+        #[arg(long, visible_aliases = ["project-id", "id"], env = "CI_PROJECT_ID")]
+        #[arg(help = "Set the ID on GitLab of this project")]
+        gitlab_project_id: u64,
+        #[arg(long, env = "CI_PROJECT_NAME")]
+        #[arg(value_parser = parse_name)]
+        #[arg(help = "Set the name of this project")]
+        project_name:      Name,
+        #[arg(long, visible_aliases = ["phase"])]
+        #[arg(value_parser = parse_phase)]
+        #[arg(help = "Set the phase")]
+        phase:             Phase,
+        #[command(flatten)]
+        host_args:         HostArgs,
+    },
+    #[command(about = "Manage the SSH host keys of the hosts in a phase")]
+    #[command(after_help = AFTER_HELP)]
+    Hosts {
+        #[command(subcommand)]
+        command: HostsCommands,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HostsCommands {
+    #[command(about = "Scan the host keys of every host in the phase (or of a single host) and \
+                       add them to the known_hosts file of this tool after the fingerprints are \
+                       confirmed or match the pinned ones")]
+    #[command(after_help = AFTER_HELP)]
+    Trust {
+        #[arg(value_name = "PHASE|USER@HOST")]
+        #[arg(value_parser = parse_trust_target)]
+        #[arg(help = "Set the phase, or a host which is in no phase (e.g. the host of \
+                      `backend-develop`). A value with `@`, a port or brackets is a host")]
+        target:      TrustTarget,
+        #[command(flatten)]
+        host_filter: HostFilterArgs,
+    },
+}
+
+impl CLICommands {
+    /// Get `--on-error` and the `--limit` and `--exclude` patterns. Only the subcommands which
+    /// handle the hosts of a phase have them.
+    pub fn get_host_options(&self) -> (Option<OnError>, Option<&HostFilterArgs>) {
+        match self {
+            CLICommands::FrontendDeploy {
+                host_args, ..
+            }
+            | CLICommands::FrontendControl {
+                host_args, ..
+            }
+            | CLICommands::FrontendRollback {
+                host_args, ..
+            }
+            | CLICommands::BackendDeploy {
+                host_args, ..
+            }
+            | CLICommands::BackendControl {
+                host_args, ..
+            }
+            | CLICommands::BackendRollback {
+                host_args, ..
+            }
+            | CLICommands::SimpleDeploy {
+                host_args, ..
+            }
+            | CLICommands::SimpleControl {
+                host_args, ..
+            }
+            | CLICommands::Promote {
+                host_args, ..
+            }
+            | CLICommands::Prune {
+                host_args, ..
+            }
+            | CLICommands::Verify {
+                host_args, ..
+            } => (host_args.on_error, Some(&host_args.host_filter)),
+            CLICommands::Status {
+                host_filter, ..
+            }
+            | CLICommands::Hosts {
+                command:
+                    HostsCommands::Trust {
+                        host_filter, ..
+                    },
+            } => (None, Some(host_filter)),
+            CLICommands::FrontendDevelop {
+                ..
+            }
+            | CLICommands::BackendDevelop {
+                ..
+            } => (None, None),
+        }
+    }
+}
+
+/// What `hosts trust` scans.
+#[derive(Debug, Clone)]
+pub enum TrustTarget {
+    Phase(Phase),
+    SshUserHost(SshUserHost),
 }
 
 #[inline]
@@ -299,6 +690,11 @@ fn parse_commit_sha(arg: &str) -> Result<CommitSha, RegexError> {
     CommitSha::parse_str(arg)
 }
 
+#[inline]
+fn parse_commit_sha_prefix(arg: &str) -> Result<CommitShaPrefix, RegexError> {
+    CommitShaPrefix::parse_str(arg)
+}
+
 #[inline]
 fn parse_name(arg: &str) -> Result<Name, RegexError> {
     Name::parse_str(arg)
@@ -334,11 +730,25 @@ fn parse_ssh_url_prefix(arg: &str) -> Result<SshUrlPrefix, RegexError> {
     SshUrlPrefix::parse_str(arg)
 }
 
+#[inline]
+fn parse_docker_registry(arg: &str) -> Result<DockerRegistry, RegexError> {
+    DockerRegistry::parse_str(arg)
+}
+
 #[inline]
 fn parse_ssh_user_host(arg: &str) -> anyhow::Result<SshUserHost> {
     SshUserHost::parse_str(arg).map_err(|_| anyhow!("{arg:?} is not a correct SSH user and host"))
 }
 
+/// A phase cannot contain `@`, `:` or brackets, so anything with them is a host.
+fn parse_trust_target(arg: &str) -> anyhow::Result<TrustTarget> {
+    if arg.contains(['@', ':', '[']) {
+        parse_ssh_user_host(arg).map(TrustTarget::SshUserHost)
+    } else {
+        Ok(TrustTarget::Phase(parse_phase(arg)?))
+    }
+}
+
 #[inline]
 fn parse_build_target(arg: &str) -> Result<BuildTarget, RegexError> {
     BuildTarget::parse_str(arg)
@@ -349,6 +759,21 @@ fn parse_command(arg: &str) -> anyhow::Result<Command> {
     Command::parse_str(arg).map_err(|_| anyhow!("{arg:?} is not a correct command"))
 }
 
+#[inline]
+fn parse_host_pattern(arg: &str) -> anyhow::Result<HostPattern> {
+    HostPattern::parse_str(arg).map_err(|_| anyhow!("The host pattern should not be empty"))
+}
+
+#[inline]
+fn parse_output_format(arg: &str) -> anyhow::Result<OutputFormat> {
+    OutputFormat::parse_str(arg).map_err(|_| anyhow!("{arg:?} is not `table` or `json`"))
+}
+
+#[inline]
+fn parse_on_error(arg: &str) -> anyhow::Result<OnError> {
+    OnError::parse_str(arg).map_err(|_| anyhow!("{arg:?} is not `continue` or `abort`"))
+}
+
 pub fn get_args() -> CLIArgs {
     let args = CLIArgs::command();
 
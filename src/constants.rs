@@ -1,3 +1,19 @@
 pub(crate) const SERVICE_DIRECTORY: &str = "services";
 pub(crate) const PROJECT_DIRECTORY: &str = "projects";
 pub(crate) const PHASE_DIRECTORY: &str = "phases";
+pub(crate) const KNOWN_HOSTS_FILE: &str = ".gitlab-deploy/known_hosts";
+
+pub(crate) const DOWNLOAD_MAX_ATTEMPTS: u32 = 4;
+
+/// In seconds.
+pub(crate) const SSH_CONTROL_PERSIST: u32 = 60;
+
+/// Recorded in `control.log` when a release fails to come up or to pass the health check.
+pub(crate) const FAILED_EVENT: &str = "failed";
+/// Recorded in `control.log` when the previous release is brought back up after a failure.
+pub(crate) const AUTO_ROLLBACK_EVENT: &str = "auto_rollback";
+/// Recorded in `control.log` for the release which a rollback moved away from.
+pub(crate) const ROLLED_BACK_EVENT: &str = "rolled_back";
+
+/// The `--format` of `docker compose ps`, `<service> <state> <health> <exit code>` per container.
+pub(crate) const COMPOSE_PS_FORMAT: &str = "{{.Service}} {{.State}} {{.Health}} {{.ExitCode}}";
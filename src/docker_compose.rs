@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::anyhow;
+use serde_yaml::{Mapping, Value};
+
+/// Rewrite the docker compose file of a release. `image_tags` maps every image built by the
+/// project to its tag in this release. A service using one of those images (without a tag) gets the
+/// tag, and its `build` is dropped because the hosts never build. Any other service must not build
+/// from a local directory, because only the docker compose file is uploaded to the release
+/// directory.
+pub(crate) fn rewrite_docker_compose(
+    docker_compose: &str,
+    image_tags: &BTreeMap<String, String>,
+) -> anyhow::Result<String> {
+    let mut document: Value = serde_yaml::from_str(docker_compose)?;
+
+    // aliases are resolved by the parser, but merge keys (`<<: *anchor`) are not
+    document.apply_merge()?;
+
+    let services = match document.get_mut("services") {
+        Some(Value::Mapping(services)) => services,
+        _ => return Err(anyhow!("`services` cannot be found")),
+    };
+
+    let mut used_image_names = HashSet::new();
+
+    for (name, service) in services.iter_mut() {
+        let name = get_service_name(name);
+
+        let service = match service {
+            Value::Mapping(service) => service,
+            _ => return Err(anyhow!("The service {name} is not a mapping")),
+        };
+
+        let image = match service.get("image") {
+            Some(Value::String(image)) => Some(image.clone()),
+            Some(_) => return Err(anyhow!("The image of the service {name} is not a string")),
+            None => None,
+        };
+
+        if let Some(image) = image {
+            if let Some(image_tag) = image_tags.get(image.as_str()) {
+                service.insert(Value::from("image"), Value::from(image_tag.as_str()));
+                service.remove("build");
+
+                used_image_names.insert(image);
+
+                continue;
+            }
+
+            if image_tags.contains_key(get_repository(image.as_str())) {
+                return Err(anyhow!(
+                    "The image {image:?} of the service {name} is built by the project, so it \
+                     must not have a tag or a digest"
+                ));
+            }
+        }
+
+        check_build_context(&name, service)?;
+    }
+
+    if let Some(image_name) = image_tags.keys().find(|key| !used_image_names.contains(*key)) {
+        return Err(anyhow!("The image {image_name} is not used by any service"));
+    }
+
+    Ok(serde_yaml::to_string(&document)?)
+}
+
+fn get_service_name(name: &Value) -> String {
+    match name.as_str() {
+        Some(name) => String::from(name),
+        None => format!("{name:?}"),
+    }
+}
+
+/// Strip the tag and the digest of an image reference. A `:` before the last `/` belongs to the
+/// port of a registry.
+fn get_repository(image: &str) -> &str {
+    let image = image.split_once('@').map(|(image, _)| image).unwrap_or(image);
+
+    match image.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    }
+}
+
+/// A service can only build from a remote context (a URL or a git repository) on the hosts.
+fn check_build_context(name: &str, service: &Mapping) -> anyhow::Result<()> {
+    let context = match service.get("build") {
+        None => return Ok(()),
+        Some(Value::String(context)) => context.as_str(),
+        Some(Value::Mapping(build)) => match build.get("context") {
+            Some(Value::String(context)) => context.as_str(),
+            Some(_) => {
+                return Err(anyhow!("The build context of the service {name} is not a string"))
+            },
+            None => ".",
+        },
+        Some(_) => return Err(anyhow!("The build of the service {name} is not correct")),
+    };
+
+    if context.contains("://") || context.starts_with("git@") {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "The service {name} builds from {context:?}, which is not in the release directory. Build \
+         its image in deploy/build.sh and add the image name to deploy/image-name.txt instead."
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_tags(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(image, tag)| (String::from(*image), String::from(*tag))).collect()
+    }
+
+    fn rewrite(docker_compose: &str, pairs: &[(&str, &str)]) -> anyhow::Result<Value> {
+        let rewritten = rewrite_docker_compose(docker_compose, &image_tags(pairs))?;
+
+        Ok(serde_yaml::from_str(rewritten.as_str()).unwrap())
+    }
+
+    fn service<'a>(document: &'a Value, name: &str) -> &'a Value {
+        &document["services"][name]
+    }
+
+    #[test]
+    fn rewrite_quoted_image() {
+        let document = rewrite("services:\n  app:\n    image: \"app\"\n    build: .\n", &[(
+            "app",
+            "app:0b14cd4f",
+        )])
+        .unwrap();
+
+        assert_eq!(Some("app:0b14cd4f"), service(&document, "app")["image"].as_str());
+        assert!(service(&document, "app").get("build").is_none());
+    }
+
+    #[test]
+    fn rewrite_inline_map() {
+        let document = rewrite(
+            "services:\n  app: { image: 'app', build: { context: . }, ports: ['80:80'] }\n",
+            &[("app", "app:0b14cd4f")],
+        )
+        .unwrap();
+
+        assert_eq!(Some("app:0b14cd4f"), service(&document, "app")["image"].as_str());
+        assert!(service(&document, "app").get("build").is_none());
+        assert_eq!(Some("80:80"), service(&document, "app")["ports"][0].as_str());
+    }
+
+    #[test]
+    fn rewrite_anchor_and_merge_key() {
+        let document = rewrite(
+            "x-app: &app\n  image: app\n  build: .\n  restart: always\nservices:\n  web:\n    <<: \
+             *app\n    command: web\n  worker:\n    <<: *app\n    command: worker\n",
+            &[("app", "app:0b14cd4f")],
+        )
+        .unwrap();
+
+        for name in ["web", "worker"] {
+            assert_eq!(Some("app:0b14cd4f"), service(&document, name)["image"].as_str());
+            assert_eq!(Some("always"), service(&document, name)["restart"].as_str());
+            assert_eq!(Some(name), service(&document, name)["command"].as_str());
+            assert!(service(&document, name).get("build").is_none());
+        }
+    }
+
+    #[test]
+    fn rewrite_several_images() {
+        let document = rewrite(
+            "services:\n  web:\n    image: web\n  worker:\n    image: worker\n  db:\n    image: \
+             postgres:16\n",
+            &[("web", "web:0b14cd4f"), ("worker", "worker:0b14cd4f")],
+        )
+        .unwrap();
+
+        assert_eq!(Some("web:0b14cd4f"), service(&document, "web")["image"].as_str());
+        assert_eq!(Some("worker:0b14cd4f"), service(&document, "worker")["image"].as_str());
+        assert_eq!(Some("postgres:16"), service(&document, "db")["image"].as_str());
+    }
+
+    #[test]
+    fn rewrite_errors() {
+        let err = rewrite("services:\n  web:\n    image: web\n", &[
+            ("web", "web:0b14cd4f"),
+            ("worker", "worker:0b14cd4f"),
+        ])
+        .unwrap_err();
+
+        assert_eq!("The image worker is not used by any service", err.to_string());
+
+        let err = rewrite("services:\n  web:\n    image: web:latest\n", &[("web", "web:0b14cd4f")])
+            .unwrap_err();
+
+        assert!(err.to_string().contains("must not have a tag or a digest"), "{err}");
+
+        let err = rewrite("services:\n  db:\n    build: ./db\n", &[]).unwrap_err();
+
+        assert!(err.to_string().contains("builds from \"./db\""), "{err}");
+    }
+
+    #[test]
+    fn repository() {
+        assert_eq!("app", get_repository("app"));
+        assert_eq!("app", get_repository("app:1.0"));
+        assert_eq!(
+            "registry.example.com:5000/app",
+            get_repository("registry.example.com:5000/app")
+        );
+        assert_eq!(
+            "registry.example.com:5000/app",
+            get_repository("registry.example.com:5000/app:1.0")
+        );
+        assert_eq!("app", get_repository("app@sha256:0b14cd4fdec3bdff"));
+        assert_eq!(
+            "registry.example.com:5000/app",
+            get_repository("registry.example.com:5000/app:1.0@sha256:0b14cd4fdec3bdff")
+        );
+    }
+
+    #[test]
+    fn build_context() {
+        let check = |build: &str| {
+            let service: Mapping =
+                serde_yaml::from_str(format!("build: {build}").as_str()).unwrap();
+
+            check_build_context("app", &service)
+        };
+
+        assert!(check("https://github.com/example/app.git#main").is_ok());
+        assert!(check("git@github.com:example/app.git").is_ok());
+        assert!(check("{ context: 'https://example.com/app.tar.gz' }").is_ok());
+        assert!(check(".").is_err());
+        assert!(check("{ dockerfile: Dockerfile }").is_err());
+        assert!(check("{ context: ./app }").is_err());
+        assert!(check_build_context("app", &Mapping::new()).is_ok());
+    }
+}
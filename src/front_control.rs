@@ -1,4 +1,4 @@
-use std::{path::PathBuf, process::Stdio};
+use std::{num::NonZeroUsize, path::PathBuf, process::Stdio};
 
 use anyhow::anyhow;
 use execute::Execute;
@@ -8,6 +8,8 @@ use crate::{
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    models::*,
+    shell::quote,
 };
 
 pub(crate) fn front_control(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -19,6 +21,7 @@ pub(crate) fn front_control(cli_args: CLIArgs) -> anyhow::Result<()> {
         project_name,
         reference_name,
         phase,
+        ..
     } = cli_args.command
     {
         check_ssh()?;
@@ -30,97 +33,139 @@ pub(crate) fn front_control(cli_args: CLIArgs) -> anyhow::Result<()> {
             return Ok(());
         }
 
-        for ssh_user_host in ssh_user_hosts.iter() {
-            log::info!("Controlling to {ssh_user_host} (apply)");
+        let release = format!(
+            "{reference_name}-{commit_sha}",
+            reference_name = reference_name.as_ref(),
+            commit_sha = commit_sha.get_short_sha(),
+        );
 
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Abort, |ssh_user_host| {
             let ssh_home = get_ssh_home(ssh_user_host)?;
 
-            let ssh_root = format!("{ssh_home}/{PROJECT_DIRECTORY}");
-
-            let ssh_project = format!(
-                "{ssh_root}/{project_name}-{project_id}/{reference_name}-{commit_sha}",
-                project_name = project_name.as_ref(),
-                reference_name = reference_name.as_ref(),
-                commit_sha = commit_sha.get_short_sha(),
-            );
-
-            let tarball_path = {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!(
-                        "find {ssh_project:?} -mindepth 1 -maxdepth 1 -iname '*.tar.zst' | head -1"
-                    ),
-                );
-
-                command.stdout(Stdio::piped());
-                command.stderr(Stdio::piped());
-
-                let output = command.execute_output()?;
-
-                if output.status.success() {
-                    let mut files = String::from_utf8(output.stdout)?;
-
-                    files.trim_in_place();
-
-                    if files.is_empty() {
-                        return Err(anyhow!(
-                            "The archive file cannot be found in the project {ssh_project:?}",
-                        ));
-                    }
-
-                    PathBuf::from(files)
-                } else {
-                    String::from_utf8_lossy(output.stderr.as_slice()).split('\n').for_each(
-                        |line| {
-                            if !line.is_empty() {
-                                log::error!("{line}");
-                            }
-                        },
-                    );
-
-                    return Err(anyhow!(
-                        "The archive file cannot be found in the project {ssh_project:?}"
-                    ));
-                }
-            };
-
-            let tarball = tarball_path.file_name().unwrap().to_string_lossy();
-            let public_name = tarball.strip_suffix(".tar.zst").unwrap();
-
-            let ssh_html_path = format!("{ssh_home}/{SERVICE_DIRECTORY}/www/{public_name}/html");
-
-            {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!(
-                        "cd {ssh_project:?} && (([ ! -d public ] && mkdir public) || true) && \
-                         (zstd -T0 -d -c {tarball:?} | tar -xf - -C public) && mkdir -p \
-                         {ssh_html_path:?} && (([ -d {ssh_html_path:?} ] && rm -r \
-                         {ssh_html_path:?}) || true) && cp -r public {ssh_html_path:?} && rm -r \
-                         public && echo \"{timestamp} apply {reference_name}-{commit_sha}\" >> \
-                         {ssh_project:?}/../control.log",
-                        reference_name = reference_name.as_ref(),
-                        timestamp = current_timestamp(),
-                        commit_sha = commit_sha.get_short_sha(),
-                    ),
-                );
-
-                let status = command.execute()?;
-
-                if let Some(0) = status {
-                    // do nothing
-                } else {
-                    return Err(anyhow!("Cannot apply the project"));
-                }
+            let ssh_project = format!("{ssh_home}/{PROJECT_DIRECTORY}/{project}/{release}");
+
+            apply_front_release(
+                ssh_user_host,
+                ssh_home.as_str(),
+                ssh_project.as_str(),
+                project.as_str(),
+                release.as_str(),
+            )
+        })?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
+
+/// The name of the directory in `services/www/<public_name>/releases` which holds the public static
+/// files of a release. `project` is `<project_name>-<project_id>`.
+#[inline]
+pub(crate) fn get_front_release_directory_name(project: &str, release: &str) -> String {
+    format!("{project}-{release}")
+}
+
+/// Apply the archive of public static files in `ssh_project` on the host. The files are extracted
+/// into their own directory in `services/www/<public_name>/releases` once, and then the `html`
+/// symlink is swapped to it atomically, so the site is never empty or half-copied and switching
+/// back to an extracted release is instant. `release` is the name of the release directory, which
+/// is recorded in `control.log`.
+pub(crate) fn apply_front_release(
+    ssh_user_host: &SshUserHost,
+    ssh_home: &str,
+    ssh_project: &str,
+    project: &str,
+    release: &str,
+) -> anyhow::Result<()> {
+    log::info!("Controlling to {ssh_user_host} (apply)");
+
+    let tarball_path = {
+        let mut command = create_ssh_command(
+            ssh_user_host,
+            format!(
+                "find {ssh_project} -mindepth 1 -maxdepth 1 -iname '*.tar.zst' | head -1",
+                ssh_project = quote(ssh_project)
+            ),
+        );
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+
+        let output = command.execute_output()?;
+
+        if output.status.success() {
+            let mut files = String::from_utf8(output.stdout)?;
+
+            files.trim_in_place();
+
+            if files.is_empty() {
+                return Err(anyhow!(
+                    "The archive file cannot be found in the project {ssh_project:?}",
+                ));
             }
 
-            log::info!("Listing the public static files...");
+            PathBuf::from(files)
+        } else {
+            String::from_utf8_lossy(output.stderr.as_slice()).split('\n').for_each(|line| {
+                if !line.is_empty() {
+                    log::error!("{line}");
+                }
+            });
 
-            list_ssh_files(ssh_user_host, ssh_html_path)?;
+            return Err(anyhow!("The archive file cannot be found in the project {ssh_project:?}"));
         }
+    };
+
+    let tarball = tarball_path.file_name().unwrap().to_string_lossy();
+    let public_name = tarball.strip_suffix(".tar.zst").unwrap();
+
+    let ssh_www_path = format!("{ssh_home}/{SERVICE_DIRECTORY}/www/{public_name}");
+    let ssh_html_path = format!("{ssh_www_path}/html");
+
+    let release_directory =
+        format!("releases/{name}", name = get_front_release_directory_name(project, release));
+
+    // The new symlink is made next to `html` first, and `mv -T` renames it over the old symlink
+    // atomically. An `html` directory of the old layout cannot be replaced by a rename, so it is
+    // moved aside right before the swap, and `html` is missing between these two renames. If the
+    // swap fails, the old directory is moved back, and it is only removed after a successful swap.
+    let command_in_ssh = format!(
+        "mkdir -p {ssh_www_path}/releases && cd {ssh_www_path} && ([ -d {release_directory} ] || \
+         (rm -rf {release_directory}.tmp && mkdir {release_directory}.tmp && (zstd -T0 -d -c \
+         {ssh_project}/{tarball} | tar -xf - -C {release_directory}.tmp) && mv \
+         {release_directory}.tmp {release_directory})) && rm -rf html.tmp && ln -s \
+         {release_directory} html.tmp && (([ -d html ] && [ ! -L html ] && mv -T html html.old) \
+         || true) && {{ mv -T html.tmp html || {{ [ -e html ] || mv -T html.old html; exit 1; }}; \
+         }} && rm -rf html.old && printf '%s\\n' {line} >> {ssh_project}/../control.log",
+        ssh_www_path = quote(&ssh_www_path),
+        release_directory = quote(&release_directory),
+        ssh_project = quote(ssh_project),
+        tarball = quote(&tarball),
+        line = quote(&format!("{timestamp} apply {release}", timestamp = current_timestamp())),
+    );
+
+    if plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+        return Ok(());
+    }
 
-        log::info!("Successfully!");
+    {
+        let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+        let status = command.execute()?;
+
+        if let Some(0) = status {
+            // do nothing
+        } else {
+            return Err(anyhow!("Cannot apply the project"));
+        }
     }
 
+    log::info!("Listing the public static files...");
+
+    list_ssh_files(ssh_user_host, ssh_html_path)?;
+
     Ok(())
 }
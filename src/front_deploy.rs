@@ -1,13 +1,16 @@
 use std::fmt::Write as FmtWrite;
 
 use anyhow::anyhow;
-use execute::Execute;
 use tempfile::tempdir;
 
 use crate::{
+    archive::*,
+    artifact_cache::ArtifactCache,
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    models::OnError,
+    shell::quote,
 };
 
 pub(crate) fn front_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -22,12 +25,17 @@ pub(crate) fn front_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
         phase,
         gitlab_api_url_prefix: api_url_prefix,
         gitlab_api_token: api_token,
+        gitlab_api_ca_file: ca_file,
+        parallel,
+        zstd_level,
+        zstd_threads,
+        artifact_cache,
+        artifact_cache_max_size,
+        artifact_cache_max_age,
+        ..
     } = cli_args.command
     {
-        check_zstd()?;
         check_ssh()?;
-        check_wget()?;
-        check_tar()?;
         check_bash()?;
 
         let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
@@ -37,21 +45,61 @@ pub(crate) fn front_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
             return Ok(());
         }
 
+        let artifact_cache = artifact_cache.map(|directory| {
+            ArtifactCache::new(directory, artifact_cache_max_size, artifact_cache_max_age)
+        });
+
+        let cached_artifacts = match artifact_cache.as_ref() {
+            Some(artifact_cache) => {
+                artifact_cache.get_front_artifacts(project_id, &commit_sha, &build_target)?
+            },
+            None => None,
+        };
+
         let temp_dir = tempdir()?;
 
-        download_and_extract_archive(
-            &temp_dir,
-            api_url_prefix,
-            api_token,
-            project_id,
-            &commit_sha,
-        )?;
+        let (deploy_dir, public_name) = match cached_artifacts {
+            Some(cached_artifacts) => cached_artifacts,
+            None => {
+                download_and_extract_archive(
+                    &temp_dir,
+                    api_url_prefix,
+                    api_token,
+                    ca_file.as_deref(),
+                    project_id,
+                    &commit_sha,
+                )?;
+
+                let public_name = check_front_deploy(&temp_dir)?;
+
+                run_front_build(&temp_dir, build_target.clone())?;
+
+                let deploy_dir = temp_dir.path().join("deploy");
+
+                if !is_dry_run() {
+                    ensure_zstd_tarball(
+                        deploy_dir.as_path(),
+                        public_name.as_ref(),
+                        zstd_level,
+                        zstd_threads,
+                    )?;
+                }
 
-        let public_name = check_front_deploy(&temp_dir)?;
+                if let Some(artifact_cache) = artifact_cache.as_ref() {
+                    artifact_cache.put_front_artifacts(
+                        project_id,
+                        &commit_sha,
+                        &build_target,
+                        deploy_dir.as_path(),
+                        &public_name,
+                    )?;
+                }
 
-        run_front_build(&temp_dir, build_target)?;
+                (deploy_dir, public_name)
+            },
+        };
 
-        for ssh_user_host in ssh_user_hosts.iter() {
+        run_on_hosts(&ssh_user_hosts, parallel, OnError::Continue, |ssh_user_host| {
             log::info!("Deploying to {ssh_user_host}");
 
             let ssh_root = {
@@ -69,15 +117,15 @@ pub(crate) fn front_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
                 commit_sha = commit_sha.get_short_sha(),
             );
 
-            {
-                let mut command =
-                    create_ssh_command(ssh_user_host, format!("mkdir -p {ssh_project:?}"));
+            let command_in_ssh =
+                format!("mkdir -p {ssh_project}", ssh_project = quote(&ssh_project));
 
-                let status = command.execute()?;
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
                     return Err(anyhow!(
                         "Cannot create the directory {ssh_project:?} for storing the archive of \
                          public static files."
@@ -85,35 +133,32 @@ pub(crate) fn front_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
                 }
             }
 
-            let tarball_path =
-                format!("deploy/{public_name}.tar.zst", public_name = public_name.as_ref());
+            let tarball_path = deploy_dir
+                .join(format!("{public_name}.tar.zst", public_name = public_name.as_ref()))
+                .to_string_lossy()
+                .into_owned();
 
             let ssh_tarball_path =
                 format!("{ssh_project}/{public_name}.tar.zst", public_name = public_name.as_ref());
 
-            {
+            if !plan_upload(ssh_user_host, tarball_path.as_str(), ssh_tarball_path.as_str()) {
                 let mut command = create_scp_command(
                     ssh_user_host,
                     tarball_path.as_str(),
                     ssh_tarball_path.as_str(),
                 );
 
-                command.current_dir(temp_dir.path());
-
-                let status = command.execute()?;
+                let status = execute_prefixed(&mut command)?;
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                if !status.success() {
                     return Err(anyhow!(
-                        "Cannot copy {tarball_path:?} to {ssh_user_host}:{ssh_tarball_path:?} \
-                         ({ssh_user_host_port}).",
-                        ssh_user_host = ssh_user_host.user_host(),
-                        ssh_user_host_port = ssh_user_host.get_port(),
+                        "Cannot copy {tarball_path:?} to {ssh_tarball_path:?} on {ssh_user_host}."
                     ));
                 }
             }
-        }
+
+            Ok(())
+        })?;
 
         log::info!("Successfully!");
     }
@@ -1,13 +1,15 @@
 use std::fmt::Write as FmtWrite;
 
 use anyhow::anyhow;
-use execute::{command_args, Execute};
+use execute::Execute;
 use tempfile::tempdir;
 
 use crate::{
+    archive::*,
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    shell::quote,
 };
 
 pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -19,13 +21,11 @@ pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
         build_target,
         gitlab_api_url_prefix: api_url_prefix,
         gitlab_api_token: api_token,
+        gitlab_api_ca_file: ca_file,
         develop_ssh_user_host: ssh_user_host,
     } = cli_args.command
     {
-        check_zstd()?;
         check_ssh()?;
-        check_wget()?;
-        check_tar()?;
         check_bash()?;
 
         let temp_dir = tempdir()?;
@@ -34,6 +34,7 @@ pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
             &temp_dir,
             api_url_prefix,
             api_token,
+            ca_file.as_deref(),
             project_id,
             &commit_sha,
         )?;
@@ -57,14 +58,15 @@ pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
 
         let ssh_html_path = format!("{ssh_root}/html");
 
-        {
-            let mut command = create_ssh_command(
-                &ssh_user_host,
-                format!(
-                    "mkdir -p {ssh_root:?} && ((test -d {ssh_html_path:?} && rm -r \
-                     {ssh_html_path:?}) || true) && mkdir -p {ssh_html_path:?}",
-                ),
-            );
+        let command_in_ssh = format!(
+            "mkdir -p {ssh_root} && ((test -d {ssh_html_path} && rm -r {ssh_html_path}) || true) \
+             && mkdir -p {ssh_html_path}",
+            ssh_root = quote(&ssh_root),
+            ssh_html_path = quote(&ssh_html_path),
+        );
+
+        if !plan_ssh(&ssh_user_host, command_in_ssh.as_str()) {
+            let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
 
             let status = command.execute()?;
 
@@ -78,20 +80,24 @@ pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
             }
         }
 
-        let tarball_path =
-            format!("deploy/{public_name}.tar.zst", public_name = public_name.as_ref());
-
-        {
-            let mut command1 = command_args!("zstd", "-T0", "-d", "-c", tarball_path);
+        let command_in_ssh =
+            format!("tar -xf - -C {ssh_html_path}", ssh_html_path = quote(&ssh_html_path));
 
-            command1.current_dir(temp_dir.path());
+        if !plan_ssh(
+            &ssh_user_host,
+            format!(
+                "{command_in_ssh} < deploy/{public_name}.tar.zst",
+                public_name = public_name.as_ref()
+            ),
+        ) {
+            let mut reader =
+                open_tarball(temp_dir.path().join("deploy").as_path(), public_name.as_ref())?;
 
-            let mut command2 =
-                create_ssh_command(&ssh_user_host, format!("tar -xf - -C {ssh_html_path:?}"));
+            let mut command = create_ssh_command(&ssh_user_host, command_in_ssh);
 
-            log::info!("Extracting {tarball_path}");
+            log::info!("Extracting the public static files");
 
-            let result = command1.execute_multiple(&mut [&mut command2])?;
+            let result = command.execute_input_reader(&mut reader)?;
 
             if let Some(0) = result {
                 // do nothing
@@ -100,9 +106,11 @@ pub(crate) fn front_develop(cli_args: CLIArgs) -> anyhow::Result<()> {
             }
         }
 
-        log::info!("Listing the public static files...");
+        if !is_dry_run() {
+            log::info!("Listing the public static files...");
 
-        list_ssh_files(&ssh_user_host, ssh_html_path)?;
+            list_ssh_files(&ssh_user_host, ssh_html_path)?;
+        }
 
         log::info!("Successfully!");
     }
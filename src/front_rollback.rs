@@ -0,0 +1,86 @@
+use std::num::NonZeroUsize;
+
+use anyhow::anyhow;
+
+use crate::{
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    front_control::apply_front_release,
+    functions::*,
+    models::OnError,
+};
+
+pub(crate) fn front_rollback(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::FrontendRollback { .. }));
+
+    if let CLICommands::FrontendRollback {
+        gitlab_project_id: project_id,
+        project_name,
+        phase,
+        to,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
+
+        if ssh_user_hosts.is_empty() {
+            log::warn!("No hosts to roll back!");
+            return Ok(());
+        }
+
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Abort, |ssh_user_host| {
+            log::info!("Rolling back {ssh_user_host}");
+
+            let ssh_home = get_ssh_home(ssh_user_host)?;
+
+            let ssh_project_root = format!("{ssh_home}/{PROJECT_DIRECTORY}/{project}");
+
+            let releases = list_ssh_releases(ssh_user_host, ssh_project_root.as_str())?;
+            let history = read_ssh_control_log(ssh_user_host, ssh_project_root.as_str())?;
+
+            let current = history
+                .iter()
+                .rev()
+                .find(|(command, _)| command == "apply")
+                .map(|(_, release)| release.as_str());
+
+            let release =
+                find_rollback_release(&releases, &history, &["apply"], current, to.as_ref())
+                    .map_err(|err| anyhow!("{err} on {ssh_user_host}"))?;
+
+            if current == Some(release.as_str()) {
+                log::info!("{release} is already applied on {ssh_user_host}");
+                return Ok(());
+            }
+
+            log::info!(
+                "Rolling back from {current} to {release}",
+                current = current.unwrap_or("(none)")
+            );
+
+            let ssh_project = format!("{ssh_project_root}/{release}");
+
+            apply_front_release(
+                ssh_user_host,
+                ssh_home.as_str(),
+                ssh_project.as_str(),
+                project.as_str(),
+                release.as_str(),
+            )?;
+
+            if let Some(current) = current {
+                append_control_log(ssh_user_host, ssh_project.as_str(), ROLLED_BACK_EVENT, current);
+            }
+
+            Ok(())
+        })?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
@@ -1,11 +1,18 @@
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
-    env,
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
     fs::{self, File},
-    io::{BufRead, BufReader, ErrorKind},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::anyhow;
@@ -14,57 +21,97 @@ use chrono::{
     Local,
 };
 use execute::{command, command_args, Execute};
-use regex::Regex;
-use scanner_rust::{ScannerError, ScannerStr};
 use slash_formatter::delete_end_slash_in_place;
 use tempfile::TempDir;
 use trim_in_place::TrimInPlace;
+use ureq::{
+    tls::{parse_pem, PemItem, RootCerts, TlsConfig},
+    Agent, BodyReader,
+};
 use validators::prelude::*;
 
-use crate::{constants::*, models::*};
+use crate::{
+    archive::*,
+    constants::*,
+    docker_compose::*,
+    inventory::*,
+    known_hosts::*,
+    logger::{get_host_prefix, set_host_prefix},
+    models::*,
+    multiplexing::add_ssh_multiplexing_args,
+    shell::{join, quote},
+};
 
-#[inline]
-pub(crate) fn check_zstd() -> anyhow::Result<()> {
-    let mut command = command!("zstd --version");
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
 
-    if command.execute_check_exit_status_code(0).is_err() {
-        return Err(anyhow!("Cannot find zstd."));
-    }
+/// The policy chosen by `--on-error`. `None` means the default of the subcommand.
+static ON_ERROR: Mutex<Option<OnError>> = Mutex::new(None);
 
-    Ok(())
-}
+/// The patterns given by `--limit` and `--exclude`.
+static HOST_FILTER: Mutex<(Vec<HostPattern>, Vec<HostPattern>)> =
+    Mutex::new((Vec::new(), Vec::new()));
+
+/// The results of `get_ssh_home` in this run.
+static SSH_HOMES: Mutex<BTreeMap<SshUserHost, String>> = Mutex::new(BTreeMap::new());
 
 #[inline]
-pub(crate) fn check_ssh() -> anyhow::Result<()> {
-    // scp should also be checked implicitly
-    let mut command = command!("ssh -V");
+pub(crate) fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
 
-    if command.execute_check_exit_status_code(0).is_err() {
-        return Err(anyhow!("Cannot find ssh."));
-    }
+#[inline]
+pub(crate) fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
 
-    Ok(())
+#[inline]
+pub(crate) fn set_on_error(on_error: Option<OnError>) {
+    *ON_ERROR.lock().unwrap() = on_error;
 }
 
 #[inline]
-pub(crate) fn check_wget() -> anyhow::Result<()> {
-    let mut command = command!("wget --version");
+pub(crate) fn set_host_filter(limit: Vec<HostPattern>, exclude: Vec<HostPattern>) {
+    *HOST_FILTER.lock().unwrap() = (limit, exclude);
+}
 
-    if command.execute_check_exit_status_code(0).is_err() {
-        return Err(anyhow!("Cannot find wget."));
+/// Keep the hosts which match any `--limit` pattern (if given) and no `--exclude` pattern. An error
+/// is returned if the hosts are all filtered out.
+pub(crate) fn filter_ssh_user_hosts(
+    ssh_user_hosts: Vec<SshUserHost>,
+) -> anyhow::Result<Vec<SshUserHost>> {
+    let (limit, exclude) = &*HOST_FILTER.lock().unwrap();
+
+    if ssh_user_hosts.is_empty() || (limit.is_empty() && exclude.is_empty()) {
+        return Ok(ssh_user_hosts);
     }
 
-    Ok(())
+    let ssh_user_hosts: Vec<SshUserHost> = ssh_user_hosts
+        .into_iter()
+        .filter(|ssh_user_host| {
+            (limit.is_empty() || limit.iter().any(|pattern| pattern.matches(ssh_user_host)))
+                && !exclude.iter().any(|pattern| pattern.matches(ssh_user_host))
+        })
+        .collect();
+
+    if ssh_user_hosts.is_empty() {
+        return Err(anyhow!("No hosts match the --limit and --exclude patterns"));
+    }
+
+    Ok(ssh_user_hosts)
 }
 
 #[inline]
-pub(crate) fn check_tar() -> anyhow::Result<()> {
-    let mut command = command!("tar --version");
+pub(crate) fn check_ssh() -> anyhow::Result<()> {
+    // scp should also be checked implicitly
+    let mut command = command!("ssh -V");
 
     if command.execute_check_exit_status_code(0).is_err() {
-        return Err(anyhow!("Cannot find tar."));
+        return Err(anyhow!("Cannot find ssh."));
     }
 
+    // every connection needs the known_hosts file
+    get_known_hosts_path()?;
+
     Ok(())
 }
 
@@ -121,7 +168,8 @@ pub(crate) fn check_back_deploy(
     temp_dir: &TempDir,
     commit_sha: &CommitSha,
     build_target: Option<&BuildTarget>,
-) -> anyhow::Result<(ImageName, String)> {
+    docker_registry: Option<&DockerRegistry>,
+) -> anyhow::Result<(Vec<ImageName>, String)> {
     let deploy_dir = temp_dir.path().join("deploy");
 
     if !deploy_dir.join("build.sh").is_file() {
@@ -136,16 +184,33 @@ pub(crate) fn check_back_deploy(
         return Err(anyhow!("deploy/develop-down.sh cannot be found in the project."));
     }
 
-    let image_name = match fs::read_to_string(deploy_dir.join("image-name.txt")) {
-        Ok(mut image_name) => {
-            image_name.trim_in_place();
+    // one image name per line
+    let image_names = match fs::read_to_string(deploy_dir.join("image-name.txt")) {
+        Ok(image_names) => {
+            let mut names: Vec<ImageName> = Vec::new();
 
-            match ImageName::parse_string(image_name) {
-                Ok(image_name) => image_name,
-                Err(_) => {
-                    return Err(anyhow!("deploy/image-name.txt is not correct"));
-                },
+            for line in image_names.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                match ImageName::parse_str(line) {
+                    Ok(image_name) => {
+                        if names.iter().all(|name| name.as_ref() != image_name.as_ref()) {
+                            names.push(image_name);
+                        }
+                    },
+                    Err(_) => {
+                        return Err(anyhow!("deploy/image-name.txt is not correct ({line:?})"));
+                    },
+                }
+            }
+
+            if names.is_empty() {
+                return Err(anyhow!("deploy/image-name.txt is empty"));
             }
+
+            names
         },
         Err(ref error) if error.kind() == ErrorKind::NotFound => {
             return Err(anyhow!("deploy/image-name.txt cannot be found in the project."));
@@ -174,22 +239,39 @@ pub(crate) fn check_back_deploy(
         Err(error) => return Err(error.into()),
     };
 
-    let regex =
-        Regex::new(&format!("(?m)^( *image: +{image_name}) *$", image_name = image_name.as_ref()))
-            .unwrap();
+    let image_tags = image_names
+        .iter()
+        .map(|image_name| {
+            (
+                String::from(image_name.as_ref()),
+                get_image_tag(image_name, commit_sha, docker_registry),
+            )
+        })
+        .collect();
 
-    if !regex.is_match(docker_compose.as_str()) {
-        return Err(anyhow!("deploy/{docker_compose_name} or deploy/image-name.txt cannot match"));
-    }
+    let docker_compose = rewrite_docker_compose(docker_compose.as_str(), &image_tags)
+        .map_err(|error| anyhow!("deploy/{docker_compose_name} is not correct: {error}"))?;
 
-    let docker_compose = regex
-        .replace_all(
-            docker_compose.as_str(),
-            format!("$1:{commit_sha}", commit_sha = commit_sha.get_short_sha()),
-        )
-        .into_owned();
+    Ok((image_names, docker_compose))
+}
 
-    Ok((image_name, docker_compose))
+/// The tag of the built image, which is `<image_name>:<short_sha>`, or
+/// `<registry>/<image_name>:<short_sha>` if it is distributed by a docker registry.
+pub(crate) fn get_image_tag(
+    image_name: &ImageName,
+    commit_sha: &CommitSha,
+    docker_registry: Option<&DockerRegistry>,
+) -> String {
+    let short_sha = commit_sha.get_short_sha();
+
+    match docker_registry {
+        Some(docker_registry) => format!(
+            "{docker_registry}/{image_name}:{short_sha}",
+            docker_registry = docker_registry.as_ref(),
+            image_name = image_name.as_ref()
+        ),
+        None => format!("{image_name}:{short_sha}", image_name = image_name.as_ref()),
+    }
 }
 
 pub(crate) fn check_back_deploy_via_ssh<S: AsRef<str>>(
@@ -210,6 +292,10 @@ pub(crate) fn check_back_deploy_via_ssh<S: AsRef<str>>(
 }
 
 pub(crate) fn run_front_build(temp_dir: &TempDir, target: BuildTarget) -> anyhow::Result<()> {
+    if plan(format_args!("run deploy/build.sh {target}", target = target.as_ref())) {
+        return Ok(());
+    }
+
     log::info!("Running deploy/build.sh");
 
     let mut command: Command = command_args!("bash", "deploy/build.sh", target.as_ref());
@@ -230,6 +316,14 @@ pub(crate) fn run_back_build(
     commit_sha: &CommitSha,
     build_target: Option<&BuildTarget>,
 ) -> anyhow::Result<()> {
+    if plan(format_args!(
+        "run deploy/build.sh {short_sha}{build_target}",
+        short_sha = commit_sha.get_short_sha(),
+        build_target = build_target.map(|t| format!(" {}", t.as_ref())).unwrap_or_default(),
+    )) {
+        return Ok(());
+    }
+
     log::info!("Running deploy/build.sh");
 
     let mut command: Command = command_args!("bash", "deploy/build.sh", commit_sha.get_short_sha());
@@ -249,48 +343,315 @@ pub(crate) fn run_back_build(
     Ok(())
 }
 
+/// Tag the built image for the docker registry and push it. If the image is not in the local
+/// docker, it is loaded from `deploy/<image_name>.tar.zst` or `deploy/<image_name>.tar` first.
+pub(crate) fn push_back_image(
+    deploy_dir: &Path,
+    image_name: &ImageName,
+    commit_sha: &CommitSha,
+    docker_registry: &DockerRegistry,
+) -> anyhow::Result<()> {
+    let image_tag = get_image_tag(image_name, commit_sha, None);
+    let registry_image_tag = get_image_tag(image_name, commit_sha, Some(docker_registry));
+
+    if plan(format_args!("push {image_tag} to {registry_image_tag}")) {
+        return Ok(());
+    }
+
+    let mut command = command_args!("docker", "image", "inspect", image_tag.as_str());
+
+    command.stdout(Stdio::null());
+    command.stderr(Stdio::null());
+
+    if command.execute_check_exit_status_code(0).is_err() {
+        log::info!("Loading the docker image {image_tag}");
+
+        let mut reader = open_tarball(deploy_dir, image_name.as_ref())?;
+
+        let mut command = command_args!("docker", "image", "load");
+
+        command.stdout(Stdio::null());
+
+        let status = command.execute_input_reader(&mut reader)?;
+
+        if let Some(0) = status {
+            // do nothing
+        } else {
+            return Err(anyhow!("Cannot load the docker image"));
+        }
+    }
+
+    let mut command =
+        command_args!("docker", "image", "tag", image_tag.as_str(), registry_image_tag.as_str());
+
+    if command.execute_check_exit_status_code(0).is_err() {
+        return Err(anyhow!("Cannot tag the docker image {image_tag} as {registry_image_tag}"));
+    }
+
+    log::info!("Pushing the docker image {registry_image_tag}");
+
+    let mut command = command_args!("docker", "image", "push", registry_image_tag.as_str());
+
+    if command.execute_check_exit_status_code(0).is_err() {
+        return Err(anyhow!("Cannot push the docker image {registry_image_tag}"));
+    }
+
+    Ok(())
+}
+
 #[inline]
 pub(crate) fn create_ssh_command<S: AsRef<str>>(
     ssh_user_host: &SshUserHost,
     command: S,
 ) -> Command {
-    command_args!(
-        "ssh",
-        "-o",
-        "StrictHostKeyChecking=no",
-        "-o",
-        "BatchMode=yes",
-        "-p",
-        ssh_user_host.get_port().to_string(),
-        ssh_user_host.user_host(),
-        command.as_ref()
-    )
+    let mut ssh_command = command!("ssh");
+
+    ssh_command.args(get_ssh_common_args());
+
+    if let Some(port) = ssh_user_host.get_port() {
+        ssh_command.args(["-p", port.to_string().as_str()]);
+    }
+
+    ssh_command.args(get_ssh_attribute_args(ssh_user_host));
+    add_ssh_multiplexing_args(&mut ssh_command, ssh_user_host);
+
+    ssh_command.arg(ssh_user_host.user_host());
+    ssh_command.arg(command.as_ref());
+
+    ssh_command
 }
 
+/// `scp -s` uploads over SFTP (OpenSSH 8.7 or later), which takes `to` as it is. The legacy
+/// protocol would let the remote shell expand it, so spaces and metacharacters would break it.
 #[inline]
 pub(crate) fn create_scp_command<F: AsRef<str>, T: AsRef<str>>(
     ssh_user_host: &SshUserHost,
     from: F,
     to: T,
 ) -> Command {
-    command_args!(
-        "scp",
-        "-o",
-        "StrictHostKeyChecking=no",
-        "-o",
-        "BatchMode=yes",
-        "-P",
-        ssh_user_host.get_port().to_string(),
-        from.as_ref(),
+    let mut scp_command = command!("scp -s");
+
+    scp_command.args(get_ssh_common_args());
+
+    if let Some(port) = ssh_user_host.get_port() {
+        scp_command.args(["-P", port.to_string().as_str()]);
+    }
+
+    scp_command.args(get_ssh_attribute_args(ssh_user_host));
+    add_ssh_multiplexing_args(&mut scp_command, ssh_user_host);
+
+    scp_command.arg(from.as_ref());
+    scp_command.arg(format!(
+        "{ssh_user_host}:{to}",
+        ssh_user_host = ssh_user_host.user_bracketed_host(),
+        to = to.as_ref()
+    ));
+
+    scp_command
+}
+
+/// Run a command whose output is shown to the user. When hosts are handled in parallel, every line
+/// the command prints is put behind the host, like the log messages, instead of being mixed with
+/// the output of the other hosts.
+#[inline]
+pub(crate) fn execute_prefixed(command: &mut Command) -> io::Result<ExitStatus> {
+    execute_prefixed_with_input(command, None)
+}
+
+/// Run a command whose output is shown to the user, like [`execute_prefixed`], and write the data
+/// of `reader` to its stdin.
+#[inline]
+pub(crate) fn execute_prefixed_input_reader(
+    command: &mut Command,
+    reader: &mut dyn Read,
+) -> io::Result<ExitStatus> {
+    execute_prefixed_with_input(command, Some(reader))
+}
+
+fn execute_prefixed_with_input(
+    command: &mut Command,
+    reader: Option<&mut dyn Read>,
+) -> io::Result<ExitStatus> {
+    let host = get_host_prefix();
+
+    if reader.is_some() {
+        command.stdin(Stdio::piped());
+    }
+
+    if host.is_some() {
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+    }
+
+    let mut child = command.spawn()?;
+
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let input_result = thread::scope(|scope| {
+        if let (Some(host), Some(stdout), Some(stderr)) = (host.as_deref(), stdout, stderr) {
+            scope.spawn(move || write_prefixed_lines(stdout, io::stdout(), host));
+            scope.spawn(move || write_prefixed_lines(stderr, io::stderr(), host));
+        }
+
+        // stdin is closed at the end, so that the command can finish
+        match (reader, stdin) {
+            (Some(reader), Some(mut stdin)) => io::copy(reader, &mut stdin).map(|_| ()),
+            _ => Ok(()),
+        }
+    });
+
+    let status = child.wait()?;
+
+    input_result?;
+
+    Ok(status)
+}
+
+/// Copy the lines of `reader` to `writer` with the host in front of each. A line is written at
+/// once, so lines of different threads are not broken up.
+fn write_prefixed_lines<R: Read, W: Write>(reader: R, mut writer: W, host: &str) {
+    for line in BufReader::new(reader).split(b'\n') {
+        let Ok(line) = line else {
+            break;
+        };
+
+        let mut buffer = Vec::with_capacity(host.len() + line.len() + 4);
+
+        buffer.push(b'[');
+        buffer.extend_from_slice(host.as_bytes());
+        buffer.extend_from_slice(b"] ");
+        buffer.extend_from_slice(line.as_slice());
+        buffer.push(b'\n');
+
+        if writer.write_all(buffer.as_slice()).is_err() {
+            break;
+        }
+    }
+}
+
+/// The options which are used by every `ssh` and `scp` connection, including the ones to jump
+/// hosts.
+fn get_ssh_common_args() -> [String; 6] {
+    [
+        String::from("-o"),
+        String::from("StrictHostKeyChecking=yes"),
+        String::from("-o"),
+        // `check_ssh` fails without HOME already, and `/dev/null` would make ssh refuse every host
         format!(
-            "{ssh_user_host}:{to}",
-            ssh_user_host = ssh_user_host.user_host(),
-            to = to.as_ref()
+            "UserKnownHostsFile={path}",
+            path = get_known_hosts_path()
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| String::from("/dev/null"))
         ),
+        String::from("-o"),
+        String::from("BatchMode=yes"),
+    ]
+}
+
+/// The arguments which are shared by `ssh` and `scp` and come from the attributes of the host.
+fn get_ssh_attribute_args(ssh_user_host: &SshUserHost) -> Vec<String> {
+    let attributes = ssh_user_host.get_attributes();
+
+    let mut args = Vec::new();
+
+    if let Some(identity_file) = attributes.identity_file.as_deref() {
+        args.push(String::from("-i"));
+        args.push(String::from(identity_file));
+    }
+
+    if let Some(jump_host) = attributes.jump_hosts.last() {
+        args.push(String::from("-o"));
+        args.push(format!(
+            "ProxyCommand={proxy_command}",
+            proxy_command = create_proxy_command(jump_host)
+        ));
+    }
+
+    args
+}
+
+/// Build a `ProxyCommand` which reaches the host through the jump host. `ProxyJump` is not used
+/// because it does not pass our options to the jump hosts. The jump hosts before this one are
+/// chained by the nested `ProxyCommand` in its own attributes.
+fn create_proxy_command(jump_host: &SshUserHost) -> String {
+    let mut args: Vec<String> = vec![String::from("ssh")];
+
+    args.extend(get_ssh_common_args());
+
+    if let Some(port) = jump_host.get_port() {
+        args.extend([String::from("-p"), port.to_string()]);
+    }
+
+    args.extend(get_ssh_attribute_args(jump_host));
+
+    // ssh expands `%` tokens in `ProxyCommand`, once at every level. `%h` and `%p` are the host
+    // name and the port after `~/.ssh/config` is applied, so an alias is resolved locally.
+    format!(
+        "{args} -W '[%h]:%p' {jump_host}",
+        args = join(args).replace('%', "%%"),
+        jump_host = quote(&jump_host.user_host()).replace('%', "%%")
     )
 }
 
+/// In dry-run mode, log `step` as a part of the plan and return `true`, which means that the caller
+/// should skip the step. Return `false` otherwise.
+pub(crate) fn plan<S: Display>(step: S) -> bool {
+    if is_dry_run() {
+        log::info!("[plan] {step}");
+
+        true
+    } else {
+        false
+    }
+}
+
+/// Plan a command which changes something on the host. See [`plan`].
+#[inline]
+pub(crate) fn plan_ssh<S: AsRef<str>>(ssh_user_host: &SshUserHost, command_in_ssh: S) -> bool {
+    plan(format_args!("ssh {ssh_user_host}: {command}", command = command_in_ssh.as_ref()))
+}
+
+/// Plan an upload of a local file to the host. See [`plan`].
+#[inline]
+pub(crate) fn plan_upload<F: AsRef<str>, T: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    from: F,
+    to: T,
+) -> bool {
+    plan(format_args!(
+        "upload {from} to {ssh_user_host}:{to}",
+        from = from.as_ref(),
+        to = to.as_ref()
+    ))
+}
+
+/// Plan a write of `content` to a file on the host. See [`plan`].
+#[inline]
+pub(crate) fn plan_write<P: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    path: P,
+    content: &str,
+) -> bool {
+    plan(format_args!(
+        "write {length} bytes to {ssh_user_host}:{path}",
+        length = content.len(),
+        path = path.as_ref()
+    ))
+}
+
+/// Get the base directory on the host. It is the home directory unless the phase inventory sets
+/// `base-directory` for the host.
 pub(crate) fn get_ssh_home(ssh_user_host: &SshUserHost) -> anyhow::Result<String> {
+    if let Some(base_directory) = ssh_user_host.get_attributes().base_directory.as_deref() {
+        return Ok(String::from(base_directory));
+    }
+
+    if let Some(home) = SSH_HOMES.lock().unwrap().get(ssh_user_host) {
+        return Ok(home.clone());
+    }
+
     let mut command = create_ssh_command(ssh_user_host, "echo $HOME");
 
     command.stdout(Stdio::piped());
@@ -314,6 +675,8 @@ pub(crate) fn get_ssh_home(ssh_user_host: &SshUserHost) -> anyhow::Result<String
 
     delete_end_slash_in_place(&mut home);
 
+    SSH_HOMES.lock().unwrap().insert(ssh_user_host.clone(), home.clone());
+
     Ok(home)
 }
 
@@ -322,7 +685,7 @@ pub(crate) fn list_ssh_files<S: AsRef<str>>(
     path: S,
 ) -> anyhow::Result<()> {
     let mut command =
-        create_ssh_command(ssh_user_host, format!("ls {path:?}", path = path.as_ref(),));
+        create_ssh_command(ssh_user_host, format!("ls {path}", path = quote(path.as_ref())));
 
     command.stderr(Stdio::piped());
 
@@ -344,7 +707,7 @@ pub(crate) fn check_file_exist<S: AsRef<str>>(
     path: S,
 ) -> anyhow::Result<bool> {
     let mut command =
-        create_ssh_command(ssh_user_host, format!("test -f {PATH:?}", PATH = path.as_ref(),));
+        create_ssh_command(ssh_user_host, format!("test -f {path}", path = quote(path.as_ref())));
 
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
@@ -373,7 +736,7 @@ pub(crate) fn check_directory_exist<S: AsRef<str>>(
     path: S,
 ) -> anyhow::Result<bool> {
     let mut command =
-        create_ssh_command(ssh_user_host, format!("test -d {PATH:?}", PATH = path.as_ref(),));
+        create_ssh_command(ssh_user_host, format!("test -d {path}", path = quote(path.as_ref())));
 
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
@@ -397,10 +760,449 @@ pub(crate) fn check_directory_exist<S: AsRef<str>>(
     Err(anyhow!("Cannot check the existence of {:?} of {}", path.as_ref(), ssh_user_host))
 }
 
+/// List the release directories (`<reference_name>-<short_sha>`) in `ssh_project_root`, the most
+/// recently modified first.
+pub(crate) fn list_ssh_releases<S: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    ssh_project_root: S,
+) -> anyhow::Result<Vec<String>> {
+    let ssh_project_root = ssh_project_root.as_ref();
+
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "if [ -d {ssh_project_root} ]; then cd {ssh_project_root} && find . -mindepth 1 \
+             -maxdepth 1 -type d -printf '%T@ %f\\n' | sort -r -n; fi",
+            ssh_project_root = quote(ssh_project_root),
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        String::from_utf8_lossy(output.stderr.as_slice()).split('\n').for_each(|line| {
+            if !line.is_empty() {
+                log::error!("{line}");
+            }
+        });
+
+        return Err(anyhow!("Cannot list the releases in {ssh_project_root:?} of {ssh_user_host}"));
+    }
+
+    let releases = String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| line.split_once(' ').map(|(_, release)| String::from(release)))
+        .collect();
+
+    Ok(releases)
+}
+
+/// Read `control.log` in `ssh_project_root` as `(command, release)` pairs in the order they were
+/// written.
+pub(crate) fn read_ssh_control_log<S: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    ssh_project_root: S,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let ssh_project_root = ssh_project_root.as_ref();
+
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "cat {ssh_project_root}/control.log 2> /dev/null || true",
+            ssh_project_root = quote(ssh_project_root)
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Cannot read the control log in {ssh_project_root:?} of {ssh_user_host}"
+        ));
+    }
+
+    let history = String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| {
+            // [timestamp] command release
+            let (_, line) = line.split_once(' ')?;
+            let (command, release) = line.rsplit_once(' ')?;
+
+            Some((String::from(command), String::from(release)))
+        })
+        .collect();
+
+    Ok(history)
+}
+
+/// Append an event about the release in `ssh_project` to `control.log`. It is only a record, so a
+/// failure is just logged.
+pub(crate) fn append_control_log(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+    event: &str,
+    release: &str,
+) {
+    let command_in_ssh = format!(
+        "printf '%s\\n' {line} >> {quoted_ssh_project}/../control.log",
+        line = quote(&format!("{timestamp} {event} {release}", timestamp = current_timestamp())),
+        quoted_ssh_project = quote(ssh_project),
+    );
+
+    if plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+        return;
+    }
+
+    let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+    match command.execute() {
+        Ok(Some(0)) => (),
+        _ => log::warn!("The control log cannot be written"),
+    }
+}
+
+/// List the docker images (`<image>:<short_sha>`) used by the docker compose file of a release. A
+/// frontend release does not have a docker compose file so nothing is listed.
+pub(crate) fn list_ssh_release_images(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+) -> anyhow::Result<Vec<String>> {
+    let short_sha = match ssh_project.rsplit_once('-') {
+        Some((_, short_sha)) => short_sha,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "if [ -f {ssh_project}/docker-compose.yml ]; then sed -n \
+             's/^[[:space:]]*image:[[:space:]]*//p' {ssh_project}/docker-compose.yml; fi",
+            ssh_project = quote(ssh_project),
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Cannot read the docker compose file in {ssh_project:?}"));
+    }
+
+    let suffix = format!(":{short_sha}");
+
+    let image_tags = String::from_utf8(output.stdout)?
+        .lines()
+        .map(|line| line.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+        .filter(|image_tag| image_tag.ends_with(suffix.as_str()))
+        .collect();
+
+    Ok(image_tags)
+}
+
+/// Read the release name in `last-up` in `ssh_project_root`.
+pub(crate) fn read_ssh_last_up<S: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    ssh_project_root: S,
+) -> anyhow::Result<Option<String>> {
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "cat {ssh_project_root}/last-up",
+            ssh_project_root = quote(ssh_project_root.as_ref())
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::null());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let mut last_up = String::from_utf8(output.stdout)?;
+
+    last_up.trim_in_place();
+
+    if last_up.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(last_up))
+    }
+}
+
+/// Pick the release which should be rolled back to. It is the release matching `to` if `to` is
+/// given, or the most recent release activated (by one of `activating_commands`) before `current`.
+/// A release which failed or which a rollback moved away from is skipped until it is activated
+/// again, so rolling back twice goes two releases back. Without such a release in `history` (e.g.
+/// the hosts were deployed to before `control.log` was written), it is the newest release in
+/// `releases` (newest first) which is older than `current`.
+pub(crate) fn find_rollback_release(
+    releases: &[String],
+    history: &[(String, String)],
+    activating_commands: &[&str],
+    current: Option<&str>,
+    to: Option<&CommitShaPrefix>,
+) -> anyhow::Result<String> {
+    if let Some(to) = to {
+        let suffix = format!("-{short_sha}", short_sha = to.get_short_sha());
+
+        let matched: Vec<&String> =
+            releases.iter().filter(|release| release.ends_with(suffix.as_str())).collect();
+
+        return match matched.len() {
+            0 => Err(anyhow!("No release of the commit {to:?} exists", to = to.as_ref())),
+            1 => Ok(matched[0].clone()),
+            _ => Err(anyhow!(
+                "More than one release of the commit {to:?} exist: {matched:?}",
+                to = to.as_ref()
+            )),
+        };
+    }
+
+    // from the newest, so a release which failed or was rolled back from is skipped until it is
+    // activated again
+    let mut skipped_releases = HashSet::new();
+
+    for (command, release) in history.iter().rev() {
+        if command == FAILED_EVENT || command == ROLLED_BACK_EVENT {
+            skipped_releases.insert(release.as_str());
+        } else if activating_commands.contains(&command.as_str())
+            && !skipped_releases.contains(release.as_str())
+            && Some(release.as_str()) != current
+            && releases.contains(release)
+        {
+            return Ok(release.clone());
+        }
+    }
+
+    let older_releases =
+        match current.and_then(|current| releases.iter().position(|release| release == current)) {
+            Some(index) => &releases[index + 1..],
+            None => releases,
+        };
+
+    older_releases
+        .iter()
+        .find(|release| {
+            Some(release.as_str()) != current && !skipped_releases.contains(release.as_str())
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!("No previous release can be found"))
+}
+
+pub(crate) fn wait_for_health_check<S: AsRef<str>>(
+    ssh_user_host: &SshUserHost,
+    ssh_project: S,
+    health_check: &HealthCheck,
+) -> anyhow::Result<()> {
+    let command_in_ssh = match &health_check.probe {
+        HealthCheckProbe::Url(url) => format!(
+            "curl -f -s -S -o /dev/null --max-time {max_time} {url}",
+            max_time = health_check.interval.as_secs().max(1),
+            url = quote(url),
+        ),
+        HealthCheckProbe::Command(command) => {
+            format!("cd {ssh_project} && {command}", ssh_project = quote(ssh_project.as_ref()))
+        },
+        HealthCheckProbe::Compose => format!(
+            "cd {ssh_project} && docker compose ps --all --format {format}",
+            ssh_project = quote(ssh_project.as_ref()),
+            format = quote(COMPOSE_PS_FORMAT),
+        ),
+    };
+
+    if plan(format_args!("wait for the health check of {ssh_user_host}: {command_in_ssh}")) {
+        return Ok(());
+    }
+
+    log::info!("Waiting for the health check of {ssh_user_host}");
+
+    let start = Instant::now();
+
+    loop {
+        let mut command = create_ssh_command(ssh_user_host, command_in_ssh.as_str());
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+
+        let output = command.execute_output()?;
+
+        let result = if !output.status.success() {
+            Err(String::from_utf8_lossy(output.stderr.as_slice()).into_owned())
+        } else if let HealthCheckProbe::Compose = health_check.probe {
+            check_compose_ps(String::from_utf8_lossy(output.stdout.as_slice()).as_ref())
+        } else {
+            Ok(())
+        };
+
+        match result {
+            Ok(()) => {
+                log::info!("The health check of {ssh_user_host} passed");
+
+                return Ok(());
+            },
+            Err(reason) => {
+                if start.elapsed() >= health_check.timeout {
+                    reason.split('\n').for_each(|line| {
+                        if !line.is_empty() {
+                            log::warn!("{line}");
+                        }
+                    });
+
+                    return Err(anyhow!(
+                        "The health check of {ssh_user_host} did not pass within {timeout} seconds",
+                        timeout = health_check.timeout.as_secs(),
+                    ));
+                }
+            },
+        }
+
+        thread::sleep(health_check.interval);
+    }
+}
+
+/// Check the output of `docker compose ps --all` in `COMPOSE_PS_FORMAT`. Every container should be
+/// running, and healthy if it has a health check, or have exited with 0 (e.g. a one-off migration).
+fn check_compose_ps(output: &str) -> Result<(), String> {
+    let mut count = 0;
+
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let mut fields = line.split(' ');
+
+        let service = fields.next().unwrap_or_default();
+        let state = fields.next().unwrap_or_default();
+        let health = fields.next().unwrap_or_default();
+        let exit_code = fields.next().unwrap_or_default();
+
+        match (state, health) {
+            ("running", "" | "healthy") => (),
+            ("exited", _) if exit_code == "0" => (),
+            ("running", _) => return Err(format!("{service} is {health}")),
+            ("exited", _) => return Err(format!("{service} exited with {exit_code}")),
+            _ => return Err(format!("{service} is {state}")),
+        }
+
+        count += 1;
+    }
+
+    if count == 0 {
+        return Err(String::from("No containers are listed by docker compose ps"));
+    }
+
+    Ok(())
+}
+
+fn create_http_agent(ca_file: Option<&Path>) -> anyhow::Result<Agent> {
+    let mut tls_config = TlsConfig::builder();
+
+    if let Some(ca_file) = ca_file {
+        let pem = fs::read(ca_file)
+            .map_err(|err| anyhow!("Cannot read the CA bundle {ca_file:?}: {err}"))?;
+
+        let mut certificates = Vec::new();
+
+        for item in parse_pem(pem.as_slice()) {
+            match item {
+                Ok(PemItem::Certificate(certificate)) => certificates.push(certificate),
+                Ok(_) => (),
+                Err(err) => return Err(anyhow!("Cannot parse the CA bundle {ca_file:?}: {err}")),
+            }
+        }
+
+        if certificates.is_empty() {
+            return Err(anyhow!("No certificate can be found in the CA bundle {ca_file:?}"));
+        }
+
+        tls_config = tls_config.root_certs(RootCerts::new_with_certs(&certificates));
+    }
+
+    let config = Agent::config_builder()
+        .tls_config(tls_config.build())
+        .timeout_connect(Some(Duration::from_secs(30)))
+        .build();
+
+    Ok(Agent::new_with_config(config))
+}
+
+/// Request `url` with the GitLab API token and pass the response body to `consume`. Connection
+/// problems, server errors and failures while consuming the body are retried with a backoff.
+fn fetch_from_gitlab<F>(
+    url: &str,
+    api_token: &ApiToken,
+    ca_file: Option<&Path>,
+    project_id: u64,
+    commit_sha: &CommitSha,
+    mut consume: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut(BodyReader<'static>) -> anyhow::Result<()>, {
+    let agent = create_http_agent(ca_file)?;
+
+    let mut backoff = Duration::from_secs(1);
+
+    for attempt in 1.. {
+        let error = match agent.get(url).header("PRIVATE-TOKEN", api_token.as_ref()).call() {
+            Ok(response) => match consume(response.into_body().into_reader()) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            },
+            Err(ureq::Error::StatusCode(status)) => match status {
+                401 => {
+                    return Err(anyhow!("The GitLab API token is not accepted (HTTP 401)"));
+                },
+                403 => {
+                    return Err(anyhow!(
+                        "The GitLab API token cannot access the project {project_id} (HTTP 403)"
+                    ));
+                },
+                404 => {
+                    return Err(anyhow!(
+                        "The project {project_id} or the commit {commit_sha:?} cannot be found on \
+                         GitLab (HTTP 404)",
+                        commit_sha = commit_sha.as_ref(),
+                    ));
+                },
+                408 | 429 | 500..=599 => anyhow!("GitLab responded with HTTP {status}"),
+                _ => return Err(anyhow!("GitLab responded with HTTP {status}")),
+            },
+            Err(
+                err @ (ureq::Error::Io(_)
+                | ureq::Error::Timeout(_)
+                | ureq::Error::HostNotFound
+                | ureq::Error::ConnectionFailed
+                | ureq::Error::Protocol(_)),
+            ) => err.into(),
+            Err(err) => return Err(err.into()),
+        };
+
+        if attempt >= DOWNLOAD_MAX_ATTEMPTS {
+            return Err(anyhow!("Fetched unsuccessfully after {attempt} attempts: {error}"));
+        }
+
+        log::warn!("{error}, retrying in {seconds} seconds", seconds = backoff.as_secs());
+
+        thread::sleep(backoff);
+
+        backoff *= 2;
+    }
+
+    unreachable!()
+}
+
 pub(crate) fn download_archive(
     temp_dir: &TempDir,
     api_url_prefix: ApiUrlPrefix,
     api_token: ApiToken,
+    ca_file: Option<&Path>,
     project_id: u64,
     commit_sha: &CommitSha,
 ) -> anyhow::Result<PathBuf> {
@@ -414,25 +1216,15 @@ pub(crate) fn download_archive(
 
     log::info!("Fetching project from {archive_url:?}");
 
-    {
-        let mut command = command_args!(
-            "wget",
-            "--no-check-certificate",
-            archive_url,
-            "--header",
-            format!("PRIVATE-TOKEN: {api_token}", api_token = api_token.as_ref()),
-            "-O",
-            archive_save_path,
-        );
+    fetch_from_gitlab(&archive_url, &api_token, ca_file, project_id, commit_sha, |mut reader| {
+        let mut file = File::create(archive_save_path.as_path())?;
 
-        let output = command.execute()?;
+        io::copy(&mut reader, &mut file)?;
 
-        if let Some(0) = output {
-            log::info!("Fetched successfully.");
-        } else {
-            return Err(anyhow!("Fetched unsuccessfully!"));
-        }
-    }
+        Ok(())
+    })?;
+
+    log::info!("Fetched successfully.");
 
     Ok(archive_save_path)
 }
@@ -441,6 +1233,7 @@ pub(crate) fn download_and_extract_archive(
     temp_dir: &TempDir,
     api_url_prefix: ApiUrlPrefix,
     api_token: ApiToken,
+    ca_file: Option<&Path>,
     project_id: u64,
     commit_sha: &CommitSha,
 ) -> anyhow::Result<()> {
@@ -452,149 +1245,336 @@ pub(crate) fn download_and_extract_archive(
 
     log::info!("Fetching project from {archive_url:?}");
 
-    {
-        let mut command1 = command_args!(
-            "wget",
-            "--no-check-certificate",
-            archive_url,
-            "--header",
-            format!("PRIVATE-TOKEN: {api_token}", api_token = api_token.as_ref()),
-            "-O",
-            "-",
-        );
+    fetch_from_gitlab(&archive_url, &api_token, ca_file, project_id, commit_sha, |mut reader| {
+        let count = extract_tar_gz(ProgressReader::new(&mut reader, "archive"), temp_dir.path())
+            .map_err(|err| anyhow!("Cannot extract the archive: {err}"))?;
 
-        let mut command2: Command = command!("tar --strip-components 1 -z -x -v -f -");
+        log::info!("Extracted {count} entries.");
 
-        command2.current_dir(temp_dir.path());
+        Ok(())
+    })?;
 
-        let output = command1.execute_multiple(&mut [&mut command2])?;
-
-        if let Some(0) = output {
-            log::info!("Fetched successfully.");
-        } else {
-            return Err(anyhow!("Fetched unsuccessfully!"));
-        }
-    }
+    log::info!("Fetched successfully.");
 
     Ok(())
 }
 
+#[inline]
 pub(crate) fn find_ssh_user_hosts(
     phase: Phase,
     project_id: u64,
-) -> anyhow::Result<HashSet<SshUserHost>> {
-    let mut home = env::var("HOME")?;
-
-    delete_end_slash_in_place(&mut home);
-
-    let phase_path = Path::new(home.as_str()).join(PHASE_DIRECTORY).join(phase.as_ref());
+) -> anyhow::Result<Vec<SshUserHost>> {
+    Ok(find_project_inventory(phase, project_id)?.ssh_user_hosts)
+}
 
-    let file = match File::open(phase_path.as_path()) {
-        Ok(f) => f,
-        Err(ref err) if err.kind() == ErrorKind::NotFound => {
-            return Err(anyhow!("{:?} is not a supported phase!", phase.as_ref()));
-        },
-        Err(err) => return Err(err.into()),
-    };
+pub(crate) fn find_project_inventory(
+    phase: Phase,
+    project_id: u64,
+) -> anyhow::Result<ProjectInventory> {
+    load_project_inventory(phase, project_id, true)
+}
 
-    let mut reader = BufReader::new(file);
+/// Find the hosts of a phase which artifacts are read from. They are not filtered by `--limit` and
+/// `--exclude`, which only select the hosts to change.
+#[inline]
+pub(crate) fn find_source_ssh_user_hosts(
+    phase: Phase,
+    project_id: u64,
+) -> anyhow::Result<Vec<SshUserHost>> {
+    Ok(load_project_inventory(phase, project_id, false)?.ssh_user_hosts)
+}
 
-    let mut map: HashMap<u64, HashSet<SshUserHost>> = HashMap::new();
+fn load_project_inventory(
+    phase: Phase,
+    project_id: u64,
+    filter_hosts: bool,
+) -> anyhow::Result<ProjectInventory> {
+    let mut inventory = load_phase_inventory(&phase)?;
 
-    let mut line_number = 0;
+    if let Some(mut project) = inventory.projects.remove(&project_id) {
+        if filter_hosts {
+            project.ssh_user_hosts = filter_ssh_user_hosts(project.ssh_user_hosts)?;
+        }
 
-    let mut line = String::new();
+        verify_pinned_host_keys(
+            project.ssh_user_hosts.iter().flat_map(|ssh_user_host| {
+                ssh_user_host.get_attributes().jump_hosts.iter().chain([ssh_user_host])
+            }),
+            &phase,
+        )?;
 
-    let mut last_project_id: Option<u64> = None;
+        Ok(project)
+    } else {
+        Err(anyhow!(
+            "The project {project_id} is not set in {phase_path:?}",
+            phase_path = inventory.path
+        ))
+    }
+}
 
-    loop {
-        line.clear();
-        line_number += 1;
+type HostResult = (anyhow::Result<()>, Duration);
+
+/// Run `f` for every host with at most `parallel` hosts at once. After a host fails, the remaining
+/// hosts are still handled or skipped according to `--on-error` (`default_on_error` if it is not
+/// set). A summary table of the results is logged at the end, and an error is returned if any host
+/// failed.
+pub(crate) fn run_on_hosts<'a, I, F>(
+    ssh_user_hosts: I,
+    parallel: NonZeroUsize,
+    default_on_error: OnError,
+    f: F,
+) -> anyhow::Result<()>
+where
+    I: IntoIterator<Item = &'a SshUserHost>,
+    F: Fn(&SshUserHost) -> anyhow::Result<()> + Sync, {
+    let ssh_user_hosts: Vec<&SshUserHost> = ssh_user_hosts.into_iter().collect();
+
+    let on_error = ON_ERROR.lock().unwrap().unwrap_or(default_on_error);
+
+    // keep the plan of every host together
+    let parallel = if is_dry_run() { NonZeroUsize::MIN } else { parallel };
+
+    // `None` for a host which is skipped
+    let results: Mutex<Vec<Option<HostResult>>> =
+        Mutex::new(ssh_user_hosts.iter().map(|_| None).collect());
+
+    let next_index = AtomicUsize::new(0);
+    let aborted = AtomicBool::new(false);
+
+    let worker = || loop {
+        if aborted.load(Ordering::Relaxed) {
+            break;
+        }
 
-        let c = reader.read_line(&mut line)?;
+        let index = next_index.fetch_add(1, Ordering::Relaxed);
 
-        if c == 0 {
+        let Some(ssh_user_host) = ssh_user_hosts.get(index) else {
             break;
+        };
+
+        if parallel.get() > 1 {
+            set_host_prefix(Some(ssh_user_host.to_string()));
         }
 
-        if let Some(index) = line.find('#') {
-            unsafe {
-                line.as_mut_vec().set_len(index);
+        let start = Instant::now();
+
+        let result = f(ssh_user_host);
+
+        if let Err(err) = result.as_ref() {
+            log::error!("{err}");
+
+            if on_error == OnError::Abort {
+                aborted.store(true, Ordering::Relaxed);
             }
         }
 
-        let mut sc = ScannerStr::new(&line);
+        set_host_prefix(None);
 
-        let project_id = match sc.next_u64() {
-            Ok(r) => match r {
-                Some(r) => r,
-                None => continue,
-            },
-            Err(err) => match err {
-                ScannerError::ParseIntError(_) => {
-                    return Err(anyhow!(
-                        "In {phase_path:?} at line {line_number}, cannot read the project id: \
-                         {err:?}",
-                    ))
-                },
-                ScannerError::IOError(err) => return Err(err.into()),
-                ScannerError::ParseFloatError(_) => unreachable!(),
-            },
-        };
+        results.lock().unwrap()[index] = Some((result, start.elapsed()));
+    };
 
-        let mut set: HashSet<SshUserHost> = HashSet::with_capacity(1);
+    thread::scope(|scope| {
+        for _ in 1..parallel.get().min(ssh_user_hosts.len()) {
+            scope.spawn(worker);
+        }
 
-        while let Some(user_host) = sc.next()? {
-            if set.is_empty() && user_host == "." {
-                if sc.next()?.is_some() {
-                    return Err(anyhow!(
-                        "In {phase_path:?} at line {line_number}, it is not correct",
-                    ));
-                }
+        worker();
+    });
 
-                match last_project_id {
-                    Some(last_project_id) => {
-                        set.extend(map.get(&last_project_id).unwrap().iter().cloned());
-                        break;
-                    },
-                    None => {
-                        return Err(anyhow!(
-                            "In {phase_path:?} at line {line_number}, should be written after the \
-                             line that you want to reference",
-                        ))
-                    },
-                }
-            }
+    let results = results.into_inner().unwrap();
 
-            let ssh_user_host = match SshUserHost::parse_str(user_host) {
-                Ok(ssh_user_host) => ssh_user_host,
-                Err(_) => {
-                    return Err(anyhow!(
-                        "In {phase_path:?} at line {line_number}, the format of {user_host:?} is \
-                         not correct",
-                    ))
-                },
-            };
+    let host_width =
+        ssh_user_hosts.iter().map(|h| h.to_string().len()).max().unwrap_or(0).max("HOST".len());
 
-            if !set.insert(ssh_user_host) {
-                return Err(anyhow!(
-                    "In {phase_path:?} at line {line_number}, {user_host:?} is duplicated",
-                ));
-            }
-        }
+    log::info!("Summary:");
+    log::info!("  {:<host_width$}  {:<7}  {:>8}  ERROR", "HOST", "STATUS", "DURATION");
+
+    let mut failed_count = 0;
+    let mut skipped_count = 0;
+
+    for (ssh_user_host, result) in ssh_user_hosts.iter().zip(results) {
+        let ssh_user_host = ssh_user_host.to_string();
+
+        match result {
+            Some((Ok(()), duration)) => log::info!(
+                "  {ssh_user_host:<host_width$}  {:<7}  {:>7.1}s",
+                "success",
+                duration.as_secs_f64()
+            ),
+            Some((Err(err), duration)) => {
+                failed_count += 1;
+
+                log::error!(
+                    "  {ssh_user_host:<host_width$}  {:<7}  {:>7.1}s  {err}",
+                    "failure",
+                    duration.as_secs_f64()
+                );
+            },
+            None => {
+                skipped_count += 1;
 
-        map.insert(project_id, set);
-        last_project_id = Some(project_id);
+                log::warn!("  {ssh_user_host:<host_width$}  {:<7}  {:>8}", "skipped", "-");
+            },
+        }
     }
 
-    if let Some(set) = map.remove(&project_id) {
-        Ok(set)
-    } else {
-        Err(anyhow!("The project {project_id} is not set in {phase_path:?}",))
+    if failed_count > 0 {
+        return Err(if skipped_count > 0 {
+            anyhow!(
+                "Failed on {failed_count} of {count} hosts ({skipped_count} skipped).",
+                count = ssh_user_hosts.len()
+            )
+        } else {
+            anyhow!("Failed on {failed_count} of {count} hosts.", count = ssh_user_hosts.len())
+        });
     }
+
+    Ok(())
 }
 
 #[inline]
 pub(crate) fn current_timestamp() -> DelayedFormat<StrftimeItems<'static>> {
     Local::now().format("[%Y-%m-%d-%H-%M-%S]")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: &[&str] = &["up"];
+
+    fn strings(releases: &[&str]) -> Vec<String> {
+        releases.iter().map(|release| String::from(*release)).collect()
+    }
+
+    fn history(events: &[(&str, &str)]) -> Vec<(String, String)> {
+        events
+            .iter()
+            .map(|(command, release)| (String::from(*command), String::from(*release)))
+            .collect()
+    }
+
+    #[test]
+    fn rollback_to_previous() {
+        let releases = strings(&["m-cccccccc", "m-bbbbbbbb", "m-aaaaaaaa"]);
+        let history = history(&[("up", "m-aaaaaaaa"), ("up", "m-bbbbbbbb"), ("up", "m-cccccccc")]);
+
+        assert_eq!(
+            "m-bbbbbbbb",
+            find_rollback_release(&releases, &history, UP, Some("m-cccccccc"), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn rollback_skips_failed() {
+        let releases = strings(&["m-cccccccc", "m-bbbbbbbb", "m-aaaaaaaa"]);
+        let history = history(&[
+            ("up", "m-aaaaaaaa"),
+            ("up", "m-bbbbbbbb"),
+            (FAILED_EVENT, "m-bbbbbbbb"),
+            ("up", "m-cccccccc"),
+        ]);
+
+        assert_eq!(
+            "m-aaaaaaaa",
+            find_rollback_release(&releases, &history, UP, Some("m-cccccccc"), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn rollback_twice_goes_further_back() {
+        let releases = strings(&["m-cccccccc", "m-bbbbbbbb", "m-aaaaaaaa"]);
+        let mut events = vec![("up", "m-aaaaaaaa"), ("up", "m-bbbbbbbb"), ("up", "m-cccccccc")];
+
+        // the first rollback, from C to B
+        events.push(("up", "m-bbbbbbbb"));
+        events.push((ROLLED_BACK_EVENT, "m-cccccccc"));
+
+        assert_eq!(
+            "m-aaaaaaaa",
+            find_rollback_release(&releases, &history(&events), UP, Some("m-bbbbbbbb"), None)
+                .unwrap()
+        );
+
+        // the second rollback, from B to A
+        events.push(("up", "m-aaaaaaaa"));
+        events.push((ROLLED_BACK_EVENT, "m-bbbbbbbb"));
+
+        assert!(find_rollback_release(&releases, &history(&events), UP, Some("m-aaaaaaaa"), None)
+            .is_err());
+
+        // C is deployed again, so it is not skipped anymore
+        events.push(("up", "m-cccccccc"));
+
+        assert_eq!(
+            "m-aaaaaaaa",
+            find_rollback_release(&releases, &history(&events), UP, Some("m-cccccccc"), None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn rollback_without_history() {
+        let releases = strings(&["m-dddddddd", "m-cccccccc", "m-bbbbbbbb", "m-aaaaaaaa"]);
+
+        // a newer release which has never been brought up is not a previous release
+        assert_eq!(
+            "m-bbbbbbbb",
+            find_rollback_release(&releases, &[], UP, Some("m-cccccccc"), None).unwrap()
+        );
+
+        assert_eq!("m-dddddddd", find_rollback_release(&releases, &[], UP, None, None).unwrap());
+
+        assert!(find_rollback_release(&releases, &[], UP, Some("m-aaaaaaaa"), None).is_err());
+
+        // only the current release has been brought up since `control.log` is written
+        let history = history(&[("up", "m-cccccccc")]);
+
+        assert_eq!(
+            "m-bbbbbbbb",
+            find_rollback_release(&releases, &history, UP, Some("m-cccccccc"), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn rollback_to_commit() {
+        let releases = strings(&["m-cccccccc", "v1-bbbbbbbb", "m-bbbbbbbb", "m-aaaaaaaa"]);
+
+        let to = CommitShaPrefix::parse_str("aaaaaaaa").unwrap();
+
+        assert_eq!(
+            "m-aaaaaaaa",
+            find_rollback_release(&releases, &[], UP, Some("m-cccccccc"), Some(&to)).unwrap()
+        );
+
+        let to = CommitShaPrefix::parse_str("bbbbbbbb").unwrap();
+
+        assert!(find_rollback_release(&releases, &[], UP, None, Some(&to)).is_err());
+
+        let to = CommitShaPrefix::parse_str("dddddddd").unwrap();
+
+        assert!(find_rollback_release(&releases, &[], UP, None, Some(&to)).is_err());
+    }
+
+    #[test]
+    fn commit_sha_prefix_matches_short_sha() {
+        let short = CommitShaPrefix::parse_str("0b14cd4f").unwrap();
+        let long = CommitShaPrefix::parse_str("0b14cd4fdec3").unwrap();
+        let full = CommitShaPrefix::parse_str("0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f").unwrap();
+
+        assert_eq!("0b14cd4f", short.get_short_sha());
+        assert_eq!("0b14cd4f", long.get_short_sha());
+        assert_eq!("0b14cd4f", full.get_short_sha());
+
+        assert!(CommitShaPrefix::parse_str("0b14cd4").is_err());
+        assert!(CommitShaPrefix::parse_str("0b14cd4fdec3bdffffdaf1de6fe13aaa01c4827f0").is_err());
+        assert!(CommitShaPrefix::parse_str("0b14cd4g").is_err());
+
+        let releases = strings(&["m-0b14cd4f", "m-aaaaaaaa"]);
+
+        assert_eq!(
+            "m-0b14cd4f",
+            find_rollback_release(&releases, &[], UP, Some("m-aaaaaaaa"), Some(&full)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    cli::{CLIArgs, CLICommands, HostsCommands, TrustTarget},
+    functions::*,
+    inventory::*,
+    known_hosts::*,
+    models::*,
+};
+
+pub(crate) fn hosts(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::Hosts { .. }));
+
+    if let CLICommands::Hosts {
+        command,
+    } = cli_args.command
+    {
+        match command {
+            HostsCommands::Trust {
+                target, ..
+            } => hosts_trust(target)?,
+        }
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
+
+fn hosts_trust(target: TrustTarget) -> anyhow::Result<()> {
+    check_ssh()?;
+
+    let ssh_user_hosts = match target {
+        TrustTarget::Phase(phase) => find_phase_ssh_user_hosts(&phase)?,
+        TrustTarget::SshUserHost(ssh_user_host) => vec![ssh_user_host],
+    };
+
+    // hosts with different users or from different projects share the same host keys, and jump
+    // hosts have to be trusted before the hosts behind them
+    let mut pins_list: Vec<(String, &SshUserHost, Vec<&String>)> = Vec::new();
+    let mut pins_indexes: HashMap<String, usize> = HashMap::new();
+
+    for ssh_user_host in ssh_user_hosts.iter().flat_map(|ssh_user_host| {
+        ssh_user_host.get_attributes().jump_hosts.iter().chain([ssh_user_host])
+    }) {
+        let name = get_known_host_name(ssh_user_host)?;
+
+        let index = *pins_indexes.entry(name.clone()).or_insert_with(|| {
+            pins_list.push((name, ssh_user_host, Vec::new()));
+
+            pins_list.len() - 1
+        });
+
+        for fingerprint in ssh_user_host.get_attributes().host_key_fingerprints.iter() {
+            let pins = &mut pins_list[index].2;
+
+            if !pins.contains(&fingerprint) {
+                pins.push(fingerprint);
+            }
+        }
+    }
+
+    if pins_list.is_empty() {
+        log::warn!("No hosts to trust!");
+        return Ok(());
+    }
+
+    let known_hosts_path = get_known_hosts_path()?;
+
+    let mut untrusted_count = 0;
+
+    for (name, ssh_user_host, pins) in pins_list {
+        log::info!("Scanning the host keys of {name}");
+
+        let mut keys = scan_host_keys(ssh_user_host)?;
+        let mut fingerprints = get_fingerprints(&keys)?;
+
+        if !pins.is_empty() {
+            let (pinned_keys, pinned_fingerprints): (Vec<String>, Vec<String>) = keys
+                .into_iter()
+                .zip(fingerprints.iter().cloned())
+                .filter(|(_, fingerprint)| pins.contains(&fingerprint))
+                .unzip();
+
+            if pinned_keys.is_empty() {
+                return Err(anyhow!(
+                    "None of the host keys of {name} ({fingerprints}) matches the fingerprints \
+                     pinned in the phase inventory",
+                    fingerprints = fingerprints.join(", ")
+                ));
+            }
+
+            keys = pinned_keys;
+            fingerprints = pinned_fingerprints;
+        }
+
+        let known_fingerprints = get_fingerprints(&find_known_host_keys(ssh_user_host)?)?;
+
+        if fingerprints.iter().any(|fingerprint| known_fingerprints.contains(fingerprint)) {
+            log::info!("The host keys of {name} are already trusted");
+            continue;
+        }
+
+        if !known_fingerprints.is_empty() {
+            return Err(anyhow!(
+                "The host keys of {name} have changed ({fingerprints}). If it is expected, remove \
+                 the old ones with `ssh-keygen -R {name:?} -f {known_hosts_path:?}` and trust the \
+                 host again.",
+                fingerprints = fingerprints.join(", ")
+            ));
+        }
+
+        if plan(format_args!(
+            "add {fingerprints} of {name} to {known_hosts_path:?}",
+            fingerprints = fingerprints.join(", ")
+        )) {
+            continue;
+        }
+
+        if pins.is_empty() {
+            for fingerprint in fingerprints.iter() {
+                log::info!("  {fingerprint}");
+            }
+
+            if !confirm(format_args!("Trust the host keys of {name} above? [y/N] "))? {
+                log::warn!("The host keys of {name} are not trusted");
+
+                untrusted_count += 1;
+
+                continue;
+            }
+        } else {
+            log::info!("The host keys of {name} match the pinned fingerprints");
+        }
+
+        add_known_host_keys(&keys)?;
+
+        log::info!("The host keys of {name} are trusted");
+    }
+
+    if untrusted_count > 0 {
+        return Err(anyhow!("{untrusted_count} hosts are not trusted."));
+    }
+
+    Ok(())
+}
+
+/// The hosts of every project in the phase which match `--limit` and `--exclude`.
+fn find_phase_ssh_user_hosts(phase: &Phase) -> anyhow::Result<Vec<SshUserHost>> {
+    let inventory = load_phase_inventory(phase)?;
+
+    // a project none of whose hosts match `--limit` and `--exclude` is fine unless every project is
+    let mut filter_error = None;
+    let mut ssh_user_hosts = Vec::new();
+
+    for project in inventory.projects.into_values() {
+        match filter_ssh_user_hosts(project.ssh_user_hosts) {
+            Ok(project_ssh_user_hosts) => ssh_user_hosts.extend(project_ssh_user_hosts),
+            Err(err) => filter_error = Some(err),
+        }
+    }
+
+    if let Some(err) = filter_error {
+        if ssh_user_hosts.is_empty() {
+            return Err(err);
+        }
+    }
+
+    Ok(ssh_user_hosts)
+}
+
+/// Ask the operator on the terminal. Anything other than `y` or `yes` (including the end of the
+/// input) means no.
+fn confirm(question: std::fmt::Arguments) -> anyhow::Result<bool> {
+    let mut stderr = io::stderr();
+
+    stderr.write_fmt(question)?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
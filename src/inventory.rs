@@ -0,0 +1,681 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use scanner_rust::{ScannerError, ScannerStr};
+use serde::Deserialize;
+use slash_formatter::delete_end_slash_in_place;
+use toml::Spanned;
+
+use crate::{constants::*, models::*};
+
+/// The hosts and settings of every project in a phase.
+#[derive(Debug)]
+pub(crate) struct PhaseInventory {
+    pub(crate) path:     PathBuf,
+    pub(crate) projects: BTreeMap<u64, ProjectInventory>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ProjectInventory {
+    /// In the order of the inventory, with hosts of a higher priority moved to the front.
+    pub(crate) ssh_user_hosts: Vec<SshUserHost>,
+    pub(crate) health_check:   Option<HealthCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TomlInventory {
+    #[serde(default)]
+    jump_hosts: Vec<Spanned<String>>,
+    #[serde(default)]
+    groups:     BTreeMap<Spanned<String>, Vec<Spanned<String>>>,
+    #[serde(default)]
+    hosts:      BTreeMap<Spanned<String>, TomlHost>,
+    #[serde(default)]
+    projects:   BTreeMap<Spanned<String>, TomlProject>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TomlHost {
+    identity_file:         Option<String>,
+    base_directory:        Option<String>,
+    #[serde(default)]
+    labels:                Vec<String>,
+    #[serde(default)]
+    priority:              i64,
+    #[serde(default)]
+    host_key_fingerprints: Vec<Spanned<String>>,
+    /// Replaces the phase-wide `jump-hosts`. An empty array means connecting directly.
+    jump_hosts:            Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TomlProject {
+    hosts:        Vec<Spanned<String>>,
+    health_check: Option<Spanned<TomlHealthCheck>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TomlHealthCheck {
+    url:      Option<String>,
+    command:  Option<String>,
+    #[serde(default)]
+    compose:  bool,
+    /// In seconds.
+    #[serde(default = "default_health_check_timeout")]
+    timeout:  u64,
+    /// In seconds.
+    #[serde(default = "default_health_check_interval")]
+    interval: u64,
+}
+
+#[inline]
+fn default_health_check_timeout() -> u64 {
+    60
+}
+
+#[inline]
+fn default_health_check_interval() -> u64 {
+    5
+}
+
+/// Load `~/phases/<phase>.toml` if it exists, or `~/phases/<phase>` otherwise.
+pub(crate) fn load_phase_inventory(phase: &Phase) -> anyhow::Result<PhaseInventory> {
+    let mut home = env::var("HOME")?;
+
+    delete_end_slash_in_place(&mut home);
+
+    let phase_directory = Path::new(home.as_str()).join(PHASE_DIRECTORY);
+
+    let toml_path = phase_directory.join(format!("{phase}.toml", phase = phase.as_ref()));
+
+    match fs::read_to_string(toml_path.as_path()) {
+        Ok(content) => parse_toml_inventory(toml_path, content.as_str(), home.as_str()),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {
+            parse_legacy_inventory(phase, phase_directory.join(phase.as_ref()))
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[inline]
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+fn parse_toml_inventory(
+    phase_path: PathBuf,
+    content: &str,
+    home: &str,
+) -> anyhow::Result<PhaseInventory> {
+    let inventory: TomlInventory = match toml::from_str(content) {
+        Ok(inventory) => inventory,
+        Err(err) => {
+            return Err(match err.span() {
+                Some(span) => anyhow!(
+                    "In {phase_path:?} at line {line_number}, {message}",
+                    line_number = line_number(content, span.start),
+                    message = err.message().trim_end(),
+                ),
+                None => anyhow!("In {phase_path:?}, {message}", message = err.message().trim_end()),
+            });
+        },
+    };
+
+    let parse_ssh_user_host = |user_host: &Spanned<String>| -> anyhow::Result<SshUserHost> {
+        SshUserHost::parse_str(user_host.get_ref()).map_err(|_| {
+            anyhow!(
+                "In {phase_path:?} at line {line_number}, the format of {user_host:?} is not \
+                 correct",
+                line_number = line_number(content, user_host.span().start),
+                user_host = user_host.get_ref(),
+            )
+        })
+    };
+
+    let mut attributes_map: HashMap<SshUserHost, SshHostAttributes> =
+        HashMap::with_capacity(inventory.hosts.len());
+
+    let mut host_jump_hosts: Vec<(SshUserHost, Vec<Spanned<String>>)> = Vec::new();
+
+    for (user_host, host) in inventory.hosts {
+        let ssh_user_host = parse_ssh_user_host(&user_host)?;
+
+        let identity_file =
+            host.identity_file.map(|identity_file| match identity_file.strip_prefix("~/") {
+                Some(path) => format!("{home}/{path}"),
+                None => identity_file,
+            });
+
+        let mut host_key_fingerprints = Vec::with_capacity(host.host_key_fingerprints.len());
+
+        for fingerprint in host.host_key_fingerprints {
+            if !fingerprint.get_ref().starts_with("SHA256:") {
+                return Err(anyhow!(
+                    "In {phase_path:?} at line {line_number}, {fingerprint:?} is not a SHA256 \
+                     fingerprint like `SHA256:...`",
+                    line_number = line_number(content, fingerprint.span().start),
+                    fingerprint = fingerprint.get_ref(),
+                ));
+            }
+
+            host_key_fingerprints.push(fingerprint.into_inner());
+        }
+
+        let attributes = SshHostAttributes {
+            identity_file,
+            base_directory: host.base_directory.map(|mut base_directory| {
+                delete_end_slash_in_place(&mut base_directory);
+
+                base_directory
+            }),
+            labels: host.labels,
+            priority: host.priority,
+            host_key_fingerprints,
+            jump_hosts: Vec::new(),
+        };
+
+        if let Some(jump_hosts) = host.jump_hosts {
+            host_jump_hosts.push((ssh_user_host.clone(), jump_hosts));
+        }
+
+        if attributes_map.insert(ssh_user_host, attributes).is_some() {
+            return Err(anyhow!(
+                "In {phase_path:?} at line {line_number}, {user_host:?} is duplicated",
+                line_number = line_number(content, user_host.span().start),
+                user_host = user_host.get_ref(),
+            ));
+        }
+    }
+
+    // every jump host gets its own attributes, and the jump hosts before it
+    let resolve_jump_hosts = |entries: &[Spanned<String>]| -> anyhow::Result<Vec<SshUserHost>> {
+        let mut jump_hosts: Vec<SshUserHost> = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            let mut jump_host = parse_ssh_user_host(entry)?;
+
+            let mut attributes = attributes_map.get(&jump_host).cloned().unwrap_or_default();
+
+            attributes.jump_hosts = jump_hosts.clone();
+
+            jump_host.set_attributes(attributes);
+
+            jump_hosts.push(jump_host);
+        }
+
+        Ok(jump_hosts)
+    };
+
+    let phase_jump_hosts = resolve_jump_hosts(&inventory.jump_hosts)?;
+
+    let mut resolved_host_jump_hosts = HashMap::with_capacity(host_jump_hosts.len());
+
+    for (ssh_user_host, entries) in host_jump_hosts {
+        resolved_host_jump_hosts.insert(ssh_user_host, resolve_jump_hosts(&entries)?);
+    }
+
+    for (ssh_user_host, attributes) in attributes_map.iter_mut() {
+        attributes.jump_hosts = match resolved_host_jump_hosts.remove(ssh_user_host) {
+            Some(jump_hosts) => jump_hosts,
+            None => phase_jump_hosts.clone(),
+        };
+    }
+
+    let mut groups: HashMap<&str, Vec<SshUserHost>> =
+        HashMap::with_capacity(inventory.groups.len());
+
+    for (group_name, user_hosts) in inventory.groups.iter() {
+        let mut group = Vec::with_capacity(user_hosts.len());
+
+        for user_host in user_hosts {
+            group.push(parse_ssh_user_host(user_host)?);
+        }
+
+        groups.insert(group_name.get_ref().as_str(), group);
+    }
+
+    let mut projects: BTreeMap<u64, ProjectInventory> = BTreeMap::new();
+
+    for (project_id, project) in inventory.projects.iter() {
+        let project_line_number = line_number(content, project_id.span().start);
+
+        let project_id = project_id.get_ref().parse::<u64>().map_err(|err| {
+            anyhow!(
+                "In {phase_path:?} at line {project_line_number}, cannot read the project id: \
+                 {err:?}",
+            )
+        })?;
+
+        let mut set: HashSet<SshUserHost> = HashSet::with_capacity(project.hosts.len());
+        let mut ssh_user_hosts: Vec<SshUserHost> = Vec::with_capacity(project.hosts.len());
+
+        for entry in project.hosts.iter() {
+            let entry_line_number = line_number(content, entry.span().start);
+
+            let entry_ssh_user_hosts = match entry.get_ref().strip_prefix('@') {
+                Some(group_name) => match groups.get(group_name) {
+                    Some(group) => group.clone(),
+                    None => {
+                        return Err(anyhow!(
+                            "In {phase_path:?} at line {entry_line_number}, the group \
+                             {group_name:?} is not defined",
+                        ));
+                    },
+                },
+                None => vec![parse_ssh_user_host(entry)?],
+            };
+
+            for mut ssh_user_host in entry_ssh_user_hosts {
+                match attributes_map.get(&ssh_user_host) {
+                    Some(attributes) => ssh_user_host.set_attributes(attributes.clone()),
+                    None if !phase_jump_hosts.is_empty() => {
+                        ssh_user_host.set_attributes(SshHostAttributes {
+                            jump_hosts: phase_jump_hosts.clone(),
+                            ..SshHostAttributes::default()
+                        })
+                    },
+                    None => (),
+                }
+
+                if !set.insert(ssh_user_host.clone()) {
+                    return Err(anyhow!(
+                        "In {phase_path:?} at line {entry_line_number}, {user_host:?} is \
+                         duplicated",
+                        user_host = ssh_user_host.to_string(),
+                    ));
+                }
+
+                ssh_user_hosts.push(ssh_user_host);
+            }
+        }
+
+        // the sort is stable, so hosts with the same priority keep their order
+        ssh_user_hosts
+            .sort_by_key(|ssh_user_host| Reverse(ssh_user_host.get_attributes().priority));
+
+        let health_check = match project.health_check.as_ref() {
+            Some(health_check) => {
+                let health_check_line_number = line_number(content, health_check.span().start);
+                let health_check = health_check.get_ref();
+
+                let probe = match (
+                    health_check.url.as_ref(),
+                    health_check.command.as_ref(),
+                    health_check.compose,
+                ) {
+                    (Some(url), None, false) => HealthCheckProbe::Url(url.clone()),
+                    (None, Some(command), false) => HealthCheckProbe::Command(command.clone()),
+                    (None, None, true) => HealthCheckProbe::Compose,
+                    _ => {
+                        return Err(anyhow!(
+                            "In {phase_path:?} at line {health_check_line_number}, exactly one of \
+                             `url`, `command` and `compose = true` should be set for the health \
+                             check",
+                        ));
+                    },
+                };
+
+                Some(HealthCheck {
+                    probe,
+                    timeout: Duration::from_secs(health_check.timeout),
+                    interval: Duration::from_secs(health_check.interval.max(1)),
+                })
+            },
+            None => None,
+        };
+
+        let project = ProjectInventory {
+            ssh_user_hosts,
+            health_check,
+        };
+
+        if projects.insert(project_id, project).is_some() {
+            return Err(anyhow!(
+                "In {phase_path:?} at line {project_line_number}, the project {project_id} is \
+                 duplicated",
+            ));
+        }
+    }
+
+    Ok(PhaseInventory {
+        path: phase_path,
+        projects,
+    })
+}
+
+fn parse_legacy_inventory(phase: &Phase, phase_path: PathBuf) -> anyhow::Result<PhaseInventory> {
+    let file = match File::open(phase_path.as_path()) {
+        Ok(f) => f,
+        Err(ref err) if err.kind() == ErrorKind::NotFound => {
+            return Err(anyhow!("{:?} is not a supported phase!", phase.as_ref()));
+        },
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut reader = BufReader::new(file);
+
+    let mut map: BTreeMap<u64, Vec<SshUserHost>> = BTreeMap::new();
+
+    let mut line_number = 0;
+
+    let mut line = String::new();
+
+    let mut last_project_id: Option<u64> = None;
+
+    loop {
+        line.clear();
+        line_number += 1;
+
+        let c = reader.read_line(&mut line)?;
+
+        if c == 0 {
+            break;
+        }
+
+        if let Some(index) = line.find('#') {
+            unsafe {
+                line.as_mut_vec().set_len(index);
+            }
+        }
+
+        let mut sc = ScannerStr::new(&line);
+
+        let project_id = match sc.next_u64() {
+            Ok(r) => match r {
+                Some(r) => r,
+                None => continue,
+            },
+            Err(err) => match err {
+                ScannerError::ParseIntError(_) => {
+                    return Err(anyhow!(
+                        "In {phase_path:?} at line {line_number}, cannot read the project id: \
+                         {err:?}",
+                    ))
+                },
+                ScannerError::IOError(err) => return Err(err.into()),
+                ScannerError::ParseFloatError(_) => unreachable!(),
+            },
+        };
+
+        let mut ssh_user_hosts: Vec<SshUserHost> = Vec::with_capacity(1);
+
+        while let Some(user_host) = sc.next()? {
+            if ssh_user_hosts.is_empty() && user_host == "." {
+                if sc.next()?.is_some() {
+                    return Err(anyhow!(
+                        "In {phase_path:?} at line {line_number}, it is not correct",
+                    ));
+                }
+
+                match last_project_id {
+                    Some(last_project_id) => {
+                        ssh_user_hosts.extend(map.get(&last_project_id).unwrap().iter().cloned());
+                        break;
+                    },
+                    None => {
+                        return Err(anyhow!(
+                            "In {phase_path:?} at line {line_number}, should be written after the \
+                             line that you want to reference",
+                        ))
+                    },
+                }
+            }
+
+            let ssh_user_host = match SshUserHost::parse_str(user_host) {
+                Ok(ssh_user_host) => ssh_user_host,
+                Err(_) => {
+                    return Err(anyhow!(
+                        "In {phase_path:?} at line {line_number}, the format of {user_host:?} is \
+                         not correct",
+                    ))
+                },
+            };
+
+            if ssh_user_hosts.contains(&ssh_user_host) {
+                return Err(anyhow!(
+                    "In {phase_path:?} at line {line_number}, {user_host:?} is duplicated",
+                ));
+            }
+
+            ssh_user_hosts.push(ssh_user_host);
+        }
+
+        map.insert(project_id, ssh_user_hosts);
+        last_project_id = Some(project_id);
+    }
+
+    let projects = map
+        .into_iter()
+        .map(|(project_id, ssh_user_hosts)| {
+            (project_id, ProjectInventory {
+                ssh_user_hosts,
+                ..ProjectInventory::default()
+            })
+        })
+        .collect();
+
+    Ok(PhaseInventory {
+        path: phase_path,
+        projects,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+    use validators::prelude::*;
+
+    use super::*;
+
+    fn parse_toml(content: &str) -> anyhow::Result<PhaseInventory> {
+        parse_toml_inventory(PathBuf::from("test.toml"), content, "/home/deploy")
+    }
+
+    fn parse_legacy(content: &str) -> anyhow::Result<PhaseInventory> {
+        let dir = tempdir().unwrap();
+        let phase_path = dir.path().join("test");
+
+        fs::write(phase_path.as_path(), content).unwrap();
+
+        parse_legacy_inventory(&Phase::parse_str("test").unwrap(), phase_path)
+    }
+
+    fn user_hosts(inventory: &PhaseInventory, project_id: u64) -> Vec<String> {
+        inventory.projects[&project_id].ssh_user_hosts.iter().map(|h| h.to_string()).collect()
+    }
+
+    fn assert_error(expected: &str, result: anyhow::Result<PhaseInventory>) {
+        let err = result.unwrap_err().to_string();
+
+        assert!(err.contains(expected), "{err:?} does not contain {expected:?}");
+    }
+
+    #[test]
+    fn toml_groups() {
+        let inventory = parse_toml(
+            r#"
+[groups]
+web = ["deploy@web1", "deploy@web2"]
+
+[projects.1]
+hosts = ["@web", "deploy@db1"]
+
+[projects.2]
+hosts = ["deploy@db1"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(vec!["deploy@web1", "deploy@web2", "deploy@db1"], user_hosts(&inventory, 1));
+        assert_eq!(vec!["deploy@db1"], user_hosts(&inventory, 2));
+        assert!(inventory.projects[&1].health_check.is_none());
+    }
+
+    #[test]
+    fn toml_hosts() {
+        let inventory = parse_toml(
+            r#"
+jump-hosts = ["deploy@bastion"]
+
+[hosts."deploy@web1"]
+identity-file = "~/.ssh/web"
+base-directory = "/srv/app/"
+labels = ["web", "eu"]
+host-key-fingerprints = ["SHA256:abc"]
+
+[hosts."deploy@web2"]
+priority = 10
+jump-hosts = []
+
+[projects.1]
+hosts = ["deploy@web1", "deploy@web2", "deploy@web3"]
+"#,
+        )
+        .unwrap();
+
+        // a higher priority first, the others in the order of the inventory
+        assert_eq!(vec!["deploy@web2", "deploy@web1", "deploy@web3"], user_hosts(&inventory, 1));
+
+        let ssh_user_hosts = &inventory.projects[&1].ssh_user_hosts;
+
+        let web2 = ssh_user_hosts[0].get_attributes();
+
+        assert_eq!(10, web2.priority);
+        assert!(web2.jump_hosts.is_empty());
+
+        let web1 = ssh_user_hosts[1].get_attributes();
+
+        assert_eq!(Some("/home/deploy/.ssh/web"), web1.identity_file.as_deref());
+        assert_eq!(Some("/srv/app"), web1.base_directory.as_deref());
+        assert_eq!(vec!["web", "eu"], web1.labels);
+        assert_eq!(vec!["SHA256:abc"], web1.host_key_fingerprints);
+        assert_eq!(
+            vec!["deploy@bastion"],
+            web1.jump_hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>()
+        );
+
+        // a host without its own `[hosts.*]` table still goes through the phase jump hosts
+        let web3 = ssh_user_hosts[2].get_attributes();
+
+        assert_eq!(None, web3.identity_file);
+        assert_eq!(
+            vec!["deploy@bastion"],
+            web3.jump_hosts.iter().map(|h| h.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn toml_health_check() {
+        let inventory = parse_toml(
+            r#"
+[projects.1]
+hosts = ["deploy@web1"]
+health-check = { url = "http://localhost/health" }
+
+[projects.2]
+hosts = ["deploy@web1"]
+health-check = { compose = true, timeout = 30, interval = 0 }
+"#,
+        )
+        .unwrap();
+
+        let health_check = inventory.projects[&1].health_check.as_ref().unwrap();
+
+        match &health_check.probe {
+            HealthCheckProbe::Url(url) => assert_eq!("http://localhost/health", url),
+            probe => panic!("{probe:?} is not a URL probe"),
+        }
+        assert_eq!(Duration::from_secs(60), health_check.timeout);
+        assert_eq!(Duration::from_secs(5), health_check.interval);
+
+        let health_check = inventory.projects[&2].health_check.as_ref().unwrap();
+
+        assert!(matches!(health_check.probe, HealthCheckProbe::Compose));
+        assert_eq!(Duration::from_secs(30), health_check.timeout);
+        assert_eq!(Duration::from_secs(1), health_check.interval);
+    }
+
+    #[test]
+    fn toml_errors() {
+        assert_error(
+            "at line 3, the group \"db\" is not defined",
+            parse_toml("[projects.1]\n\nhosts = [\"@db\"]\n"),
+        );
+        assert_error(
+            "at line 2, \"deploy@web1\" is duplicated",
+            parse_toml("[projects.1]\nhosts = [\"deploy@web1\", \"deploy@web1\"]\n"),
+        );
+        assert_error(
+            "at line 1, cannot read the project id",
+            parse_toml("[projects.web]\nhosts = []\n"),
+        );
+        assert_error(
+            "at line 2, \"MD5:abc\" is not a SHA256 fingerprint",
+            parse_toml("[hosts.\"deploy@web1\"]\nhost-key-fingerprints = [\"MD5:abc\"]\n"),
+        );
+        assert_error(
+            "at line 2, the format of \"deploy@\" is not correct",
+            parse_toml("[projects.1]\nhosts = [\"deploy@\"]\n"),
+        );
+        assert_error(
+            "at line 3, exactly one of `url`, `command` and `compose = true` should be set",
+            parse_toml(
+                "[projects.1]\nhosts = []\nhealth-check = { url = \"http://localhost\", compose = \
+                 true }\n",
+            ),
+        );
+        assert_error("In \"test.toml\" at line 2,", parse_toml("[projects.1]\nhost = []\n"));
+    }
+
+    #[test]
+    fn legacy() {
+        let inventory = parse_legacy(
+            "# project hosts\n1 deploy@web1 deploy@web2 # the web servers\n\n2 .\n3 deploy@db1\n",
+        )
+        .unwrap();
+
+        assert_eq!(vec!["deploy@web1", "deploy@web2"], user_hosts(&inventory, 1));
+        assert_eq!(vec!["deploy@web1", "deploy@web2"], user_hosts(&inventory, 2));
+        assert_eq!(vec!["deploy@db1"], user_hosts(&inventory, 3));
+    }
+
+    #[test]
+    fn legacy_errors() {
+        assert_error(
+            "at line 1, should be written after the line that you want to reference",
+            parse_legacy("1 .\n"),
+        );
+        assert_error(
+            "at line 2, it is not correct",
+            parse_legacy("1 deploy@web1\n2 . deploy@web2\n"),
+        );
+        assert_error("at line 1, cannot read the project id", parse_legacy("web deploy@web1\n"));
+        assert_error(
+            "at line 1, the format of \"deploy@\" is not correct",
+            parse_legacy("1 deploy@\n"),
+        );
+        assert_error(
+            "at line 1, \"deploy@web1\" is duplicated",
+            parse_legacy("1 deploy@web1 deploy@web1\n"),
+        );
+
+        let dir = tempdir().unwrap();
+
+        assert_error(
+            "\"test\" is not a supported phase!",
+            parse_legacy_inventory(&Phase::parse_str("test").unwrap(), dir.path().join("test")),
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    process::Stdio,
+};
+
+use anyhow::anyhow;
+use execute::{command_args, Execute};
+
+use crate::{constants::*, functions::create_ssh_command, models::*, shell::join};
+
+/// The known_hosts file which is managed by `hosts trust` and used by every SSH connection.
+pub(crate) fn get_known_hosts_path() -> anyhow::Result<PathBuf> {
+    let home = env::var_os("HOME")
+        .ok_or_else(|| anyhow!("Cannot find the known_hosts file because HOME is not set."))?;
+
+    Ok(PathBuf::from(home).join(KNOWN_HOSTS_FILE))
+}
+
+/// The host name and the port which `ssh` connects to, after `~/.ssh/config` is applied.
+pub(crate) fn resolve_ssh_host(ssh_user_host: &SshUserHost) -> anyhow::Result<(String, u16)> {
+    let mut command = command_args!("ssh", "-G");
+
+    if let Some(port) = ssh_user_host.get_port() {
+        command.args(["-p", port.to_string().as_str()]);
+    }
+
+    command.arg(ssh_user_host.user_host());
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Cannot read the SSH configuration of {ssh_user_host}: {error}",
+            error = String::from_utf8_lossy(output.stderr.as_slice()).trim()
+        ));
+    }
+
+    let mut host = None;
+    let mut port = None;
+
+    for line in String::from_utf8(output.stdout)?.lines() {
+        match line.split_once(' ') {
+            Some(("hostname", value)) => host = Some(String::from(value)),
+            Some(("port", value)) => port = value.parse::<u16>().ok(),
+            _ => (),
+        }
+    }
+
+    match (host, port) {
+        (Some(host), Some(port)) => Ok((host, port)),
+        _ => Err(anyhow!("Cannot read the SSH configuration of {ssh_user_host}")),
+    }
+}
+
+/// The name of the host in known_hosts files, which is `host` for port 22 or `[host]:port`.
+pub(crate) fn get_known_host_name(ssh_user_host: &SshUserHost) -> anyhow::Result<String> {
+    let (host, port) = resolve_ssh_host(ssh_user_host)?;
+
+    if port == 22 {
+        Ok(host)
+    } else {
+        Ok(format!("[{host}]:{port}"))
+    }
+}
+
+/// Find the known_hosts lines of the host in the managed known_hosts file.
+pub(crate) fn find_known_host_keys(ssh_user_host: &SshUserHost) -> anyhow::Result<Vec<String>> {
+    let known_hosts_path = get_known_hosts_path()?;
+
+    if !known_hosts_path.is_file() {
+        return Ok(Vec::new());
+    }
+
+    let mut command = command_args!(
+        "ssh-keygen",
+        "-F",
+        get_known_host_name(ssh_user_host)?,
+        "-f",
+        known_hosts_path
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8(output.stdout)?
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect()),
+        Some(1) => Ok(Vec::new()),
+        _ => Err(anyhow!(
+            "Cannot read {known_hosts_path:?}: {error}",
+            error = String::from_utf8_lossy(output.stderr.as_slice()).trim()
+        )),
+    }
+}
+
+/// Fetch the public keys of the host by `ssh-keyscan`, as known_hosts lines. A host behind jump
+/// hosts is scanned from the last jump host, which must be trusted already.
+pub(crate) fn scan_host_keys(ssh_user_host: &SshUserHost) -> anyhow::Result<Vec<String>> {
+    let (host, port) = resolve_ssh_host(ssh_user_host)?;
+    let port = port.to_string();
+
+    let mut command = match ssh_user_host.get_attributes().jump_hosts.last() {
+        Some(jump_host) => create_ssh_command(
+            jump_host,
+            join(["ssh-keyscan", "-T", "10", "-p", port.as_str(), host.as_str()]),
+        ),
+        None => command_args!("ssh-keyscan", "-T", "10", "-p", port, host),
+    };
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::null());
+
+    let output = command.execute_output()?;
+
+    let lines: Vec<String> = String::from_utf8(output.stdout)?
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    if lines.is_empty() {
+        return Err(anyhow!("Cannot get the host keys of {ssh_user_host}"));
+    }
+
+    Ok(lines)
+}
+
+/// Get the `SHA256:...` fingerprint of each known_hosts line.
+pub(crate) fn get_fingerprints(lines: &[String]) -> anyhow::Result<Vec<String>> {
+    if lines.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut command = command_args!("ssh-keygen", "-l", "-E", "sha256", "-f", "-");
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_input_output(format!("{}\n", lines.join("\n")).as_str())?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Cannot get the fingerprints of the host keys: {error}",
+            error = String::from_utf8_lossy(output.stderr.as_slice()).trim()
+        ));
+    }
+
+    // bits fingerprint comment (type)
+    let fingerprints: Vec<String> = String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1).map(String::from))
+        .collect();
+
+    if fingerprints.len() != lines.len() {
+        return Err(anyhow!("Cannot get the fingerprints of the host keys"));
+    }
+
+    Ok(fingerprints)
+}
+
+/// Append known_hosts lines to the managed known_hosts file.
+pub(crate) fn add_known_host_keys(lines: &[String]) -> anyhow::Result<()> {
+    let known_hosts_path = get_known_hosts_path()?;
+
+    if let Some(parent) = known_hosts_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(known_hosts_path)?;
+
+    for line in lines {
+        writeln!(file, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// Make sure that the trusted host keys of the hosts whose fingerprints are pinned in the phase
+/// inventory match the pins.
+pub(crate) fn verify_pinned_host_keys<'a, I: IntoIterator<Item = &'a SshUserHost>>(
+    ssh_user_hosts: I,
+    phase: &Phase,
+) -> anyhow::Result<()> {
+    for ssh_user_host in ssh_user_hosts {
+        let pins = &ssh_user_host.get_attributes().host_key_fingerprints;
+
+        if pins.is_empty() {
+            continue;
+        }
+
+        let fingerprints = get_fingerprints(&find_known_host_keys(ssh_user_host)?)?;
+
+        if fingerprints.is_empty() {
+            return Err(anyhow!(
+                "The host key of {ssh_user_host} is not trusted yet. Run `gitlab-deploy hosts \
+                 trust {phase}` first.",
+                phase = phase.as_ref()
+            ));
+        }
+
+        if let Some(fingerprint) = fingerprints.iter().find(|f| !pins.contains(f)) {
+            return Err(anyhow!(
+                "The trusted host key {fingerprint} of {ssh_user_host} does not match the \
+                 fingerprints pinned in the phase inventory"
+            ));
+        }
+    }
+
+    Ok(())
+}
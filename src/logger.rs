@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::TermLogger;
+
+thread_local! {
+    static HOST_PREFIX: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A logger which puts the host handled by the current thread in front of every message.
+struct HostPrefixLogger {
+    inner: Box<TermLogger>,
+}
+
+impl Log for HostPrefixLogger {
+    #[inline]
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        HOST_PREFIX.with(|host_prefix| match host_prefix.borrow().as_deref() {
+            Some(host) => self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{host}] {args}", args = record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        })
+    }
+
+    #[inline]
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn init_logger() {
+    let mut log_config = simplelog::ConfigBuilder::new();
+
+    log_config.set_time_level(LevelFilter::Debug);
+
+    let inner = TermLogger::new(
+        LevelFilter::Info,
+        log_config.build(),
+        simplelog::TerminalMode::Mixed,
+        simplelog::ColorChoice::Auto,
+    );
+
+    log::set_boxed_logger(Box::new(HostPrefixLogger {
+        inner,
+    }))
+    .unwrap();
+
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Set (or clear) the host which is put in front of the log messages of the current thread.
+#[inline]
+pub(crate) fn set_host_prefix(host: Option<String>) {
+    HOST_PREFIX.with(|host_prefix| *host_prefix.borrow_mut() = host);
+}
+
+/// Get the host which is put in front of the log messages of the current thread, if any.
+#[inline]
+pub(crate) fn get_host_prefix() -> Option<String> {
+    HOST_PREFIX.with(|host_prefix| host_prefix.borrow().clone())
+}
@@ -1,43 +1,77 @@
+mod archive;
+mod artifact_cache;
+mod checksum;
 mod cli;
 
 mod constants;
+mod docker_compose;
 mod functions;
+mod inventory;
+mod known_hosts;
+mod logger;
 mod models;
+mod multiplexing;
+mod shell;
 
 mod back_control;
 mod back_deploy;
 mod back_develop;
+mod back_rollback;
 mod front_control;
 mod front_deploy;
 mod front_develop;
+mod front_rollback;
+mod hosts;
+mod promote;
+mod prune;
 mod simple_control;
 mod simple_deploy;
+mod status;
+mod verify;
 
 use back_control::*;
 use back_deploy::*;
 use back_develop::*;
+use back_rollback::*;
 use cli::*;
 use front_control::*;
 use front_deploy::*;
 use front_develop::*;
+use front_rollback::*;
+use functions::{set_dry_run, set_host_filter, set_on_error};
+use hosts::*;
+use logger::init_logger;
+use multiplexing::close_ssh_connections;
+use promote::*;
+use prune::*;
 use simple_control::*;
 use simple_deploy::*;
+use status::*;
+use verify::*;
 
 fn main() -> anyhow::Result<()> {
     let args = get_args();
 
-    let mut log_config = simplelog::ConfigBuilder::new();
+    init_logger();
 
-    log_config.set_time_level(simplelog::LevelFilter::Debug);
+    set_dry_run(args.dry_run);
 
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Info,
-        log_config.build(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )
-    .unwrap();
+    let (on_error, host_filter) = args.command.get_host_options();
 
+    set_on_error(on_error);
+
+    if let Some(host_filter) = host_filter {
+        set_host_filter(host_filter.limit.clone(), host_filter.exclude.clone());
+    }
+
+    let result = run(args);
+
+    close_ssh_connections();
+
+    result
+}
+
+fn run(args: CLIArgs) -> anyhow::Result<()> {
     match &args.command {
         CLICommands::FrontendDevelop {
             ..
@@ -54,6 +88,11 @@ fn main() -> anyhow::Result<()> {
         } => {
             front_control(args)?;
         },
+        CLICommands::FrontendRollback {
+            ..
+        } => {
+            front_rollback(args)?;
+        },
         CLICommands::BackendDevelop {
             ..
         } => {
@@ -69,6 +108,11 @@ fn main() -> anyhow::Result<()> {
         } => {
             back_control(args)?;
         },
+        CLICommands::BackendRollback {
+            ..
+        } => {
+            back_rollback(args)?;
+        },
         CLICommands::SimpleDeploy {
             ..
         } => {
@@ -79,6 +123,31 @@ fn main() -> anyhow::Result<()> {
         } => {
             simple_control(args)?;
         },
+        CLICommands::Promote {
+            ..
+        } => {
+            promote(args)?;
+        },
+        CLICommands::Prune {
+            ..
+        } => {
+            prune(args)?;
+        },
+        CLICommands::Status {
+            ..
+        } => {
+            status(args)?;
+        },
+        CLICommands::Verify {
+            ..
+        } => {
+            verify(args)?;
+        },
+        CLICommands::Hosts {
+            ..
+        } => {
+            hosts(args)?;
+        },
     }
 
     Ok(())
@@ -0,0 +1,21 @@
+use validators::prelude::*;
+
+/// The sha of a commit with at least its first 8 hex digits, e.g. `$CI_COMMIT_SHA`. Releases are
+/// matched by the short sha, which is all that the name of a release keeps.
+#[derive(Debug, Clone, Validator)]
+#[validator(regex(regex("^[0-9a-fA-F]{8,40}$")))]
+pub(crate) struct CommitShaPrefix(String);
+
+impl CommitShaPrefix {
+    #[inline]
+    pub(crate) fn get_short_sha(&self) -> &str {
+        &self.0[..8]
+    }
+}
+
+impl AsRef<str> for CommitShaPrefix {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
@@ -0,0 +1,15 @@
+use validators::prelude::*;
+
+/// `host[:port][/path]`, the prefix of the repositories which images are pushed to.
+#[derive(Debug, Clone, Validator)]
+#[validator(regex(regex(
+    r"^[a-zA-Z0-9](?:[a-zA-Z0-9.\-]*[a-zA-Z0-9])?(?::[0-9]{1,5})?(?:/[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*)*$"
+)))]
+pub(crate) struct DockerRegistry(String);
+
+impl AsRef<str> for DockerRegistry {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
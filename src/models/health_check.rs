@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub(crate) enum HealthCheckProbe {
+    /// An HTTP URL requested on the host, which should respond with a 2xx status code.
+    Url(String),
+    /// A command run in the release directory on the host, which should exit with 0.
+    Command(String),
+    /// `docker compose ps` in the release directory on the host, which should list every container
+    /// as running (and healthy if it has a health check) or exited with 0.
+    Compose,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HealthCheck {
+    pub(crate) probe:    HealthCheckProbe,
+    pub(crate) timeout:  Duration,
+    pub(crate) interval: Duration,
+}
@@ -0,0 +1,149 @@
+use crate::models::SshUserHost;
+
+/// A pattern given by `--limit` or `--exclude`. `*` matches any characters and `?` matches one
+/// character.
+#[derive(Debug, Clone)]
+pub(crate) struct HostPattern(String);
+
+impl HostPattern {
+    #[inline]
+    pub(crate) fn parse_str<S: Into<String>>(s: S) -> Result<Self, ()> {
+        let s = s.into();
+
+        if s.is_empty() {
+            return Err(());
+        }
+
+        Ok(HostPattern(s))
+    }
+
+    /// Whether the pattern matches the host, `user@host`, the host with its port as written in an
+    /// inventory, or one of the labels of the host.
+    pub(crate) fn matches(&self, ssh_user_host: &SshUserHost) -> bool {
+        self.matches_str(ssh_user_host.get_host())
+            || self.matches_str(ssh_user_host.user_host().as_str())
+            || self.matches_str(ssh_user_host.to_string().as_str())
+            || ssh_user_host.get_attributes().labels.iter().any(|label| self.matches_str(label))
+    }
+
+    fn matches_str(&self, s: &str) -> bool {
+        let pattern: Vec<char> = self.0.chars().collect();
+        let s: Vec<char> = s.chars().collect();
+
+        let (mut p, mut i) = (0, 0);
+
+        // the positions to retry from when the last `*` has to match one more character
+        let mut star: Option<(usize, usize)> = None;
+
+        while i < s.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, i));
+                    p += 1;
+                },
+                Some(c) if *c == '?' || *c == s[i] => {
+                    p += 1;
+                    i += 1;
+                },
+                _ => match star {
+                    Some((star_p, star_i)) => {
+                        star = Some((star_p, star_i + 1));
+                        p = star_p + 1;
+                        i = star_i + 1;
+                    },
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[p..].iter().all(|c| *c == '*')
+    }
+}
+
+impl AsRef<str> for HostPattern {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SshHostAttributes;
+
+    fn pattern(s: &str) -> HostPattern {
+        HostPattern::parse_str(s).unwrap()
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert!(HostPattern::parse_str("").is_err());
+    }
+
+    #[test]
+    fn match_literal() {
+        assert!(pattern("web1").matches_str("web1"));
+        assert!(!pattern("web1").matches_str("web12"));
+        assert!(!pattern("web12").matches_str("web1"));
+    }
+
+    #[test]
+    fn match_star() {
+        assert!(pattern("*").matches_str(""));
+        assert!(pattern("*").matches_str("web1"));
+        assert!(pattern("web*.example.com").matches_str("web.example.com"));
+        assert!(pattern("web*.example.com").matches_str("web12.example.com"));
+        assert!(!pattern("web*.example.com").matches_str("db1.example.com"));
+        assert!(pattern("*.example.com").matches_str("web1.example.com"));
+        assert!(!pattern("*.example.com").matches_str("example.com"));
+    }
+
+    #[test]
+    fn match_trailing_star() {
+        assert!(pattern("web*").matches_str("web"));
+        assert!(pattern("web*").matches_str("web1.example.com"));
+        assert!(pattern("web**").matches_str("web1"));
+        assert!(!pattern("web*").matches_str("db1"));
+    }
+
+    #[test]
+    fn match_question_mark() {
+        assert!(pattern("web?").matches_str("web1"));
+        assert!(!pattern("web?").matches_str("web"));
+        assert!(!pattern("web?").matches_str("web12"));
+        assert!(pattern("w?b?").matches_str("wab1"));
+        assert!(pattern("?*").matches_str("w"));
+        assert!(!pattern("?*").matches_str(""));
+    }
+
+    #[test]
+    fn match_multiple_stars() {
+        assert!(pattern("a*b*c").matches_str("abc"));
+        assert!(pattern("a*b*c").matches_str("axxbyyc"));
+        assert!(pattern("a*b*c").matches_str("abbbc"));
+        assert!(pattern("a*b*c").matches_str("ab_b_c"));
+        assert!(!pattern("a*b*c").matches_str("acb"));
+        assert!(!pattern("a*b*c").matches_str("abcd"));
+        assert!(pattern("a*b*c").matches_str("abcc"));
+    }
+
+    #[test]
+    fn match_ssh_user_host() {
+        let mut ssh_user_host = SshUserHost::parse_str("deploy@web1.example.com:2222").unwrap();
+
+        assert!(pattern("web1.example.com").matches(&ssh_user_host));
+        assert!(pattern("deploy@web1*").matches(&ssh_user_host));
+        assert!(pattern("*:2222").matches(&ssh_user_host));
+        assert!(!pattern("canary").matches(&ssh_user_host));
+
+        ssh_user_host.set_attributes(SshHostAttributes {
+            labels: vec!["canary".to_string(), "eu-west".to_string()],
+            ..SshHostAttributes::default()
+        });
+
+        assert!(pattern("canary").matches(&ssh_user_host));
+        assert!(pattern("eu-*").matches(&ssh_user_host));
+        assert!(!pattern("us-*").matches(&ssh_user_host));
+    }
+}
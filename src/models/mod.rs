@@ -3,11 +3,18 @@ mod api_url_prefix;
 mod build_target;
 mod command;
 mod commit_sha;
+mod commit_sha_prefix;
+mod docker_registry;
+mod health_check;
+mod host_pattern;
 mod image_name;
 mod name;
+mod on_error;
+mod output_format;
 mod phase;
 mod project_path;
 mod reference;
+mod ssh_host_attributes;
 mod ssh_url_prefix;
 mod ssh_user_host;
 
@@ -16,10 +23,17 @@ pub(crate) use api_url_prefix::*;
 pub(crate) use build_target::*;
 pub(crate) use command::*;
 pub(crate) use commit_sha::*;
+pub(crate) use commit_sha_prefix::*;
+pub(crate) use docker_registry::*;
+pub(crate) use health_check::*;
+pub(crate) use host_pattern::*;
 pub(crate) use image_name::*;
 pub(crate) use name::*;
+pub(crate) use on_error::*;
+pub(crate) use output_format::*;
 pub(crate) use phase::*;
 pub(crate) use project_path::*;
 pub(crate) use reference::*;
+pub(crate) use ssh_host_attributes::*;
 pub(crate) use ssh_url_prefix::*;
 pub(crate) use ssh_user_host::*;
@@ -0,0 +1,21 @@
+/// What to do with the remaining hosts after a host fails.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum OnError {
+    Continue,
+    Abort,
+}
+
+impl OnError {
+    #[inline]
+    pub(crate) fn parse_str<S: AsRef<str>>(s: S) -> Result<Self, ()> {
+        let s = s.as_ref();
+
+        let on_error = match s.to_ascii_lowercase().as_str() {
+            "continue" => OnError::Continue,
+            "abort" => OnError::Abort,
+            _ => return Err(()),
+        };
+
+        Ok(on_error)
+    }
+}
@@ -0,0 +1,21 @@
+/// The format of the output of `status`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl OutputFormat {
+    #[inline]
+    pub(crate) fn parse_str<S: AsRef<str>>(s: S) -> Result<Self, ()> {
+        let s = s.as_ref();
+
+        let output_format = match s.to_ascii_lowercase().as_str() {
+            "table" => OutputFormat::Table,
+            "json" => OutputFormat::Json,
+            _ => return Err(()),
+        };
+
+        Ok(output_format)
+    }
+}
@@ -0,0 +1,16 @@
+use crate::models::SshUserHost;
+
+/// Optional per-host settings which can be given by a TOML phase inventory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SshHostAttributes {
+    pub(crate) identity_file:         Option<String>,
+    pub(crate) base_directory:        Option<String>,
+    pub(crate) labels:                Vec<String>,
+    /// Hosts with a higher priority are handled first. The others keep the order of the inventory.
+    pub(crate) priority:              i64,
+    /// `SHA256:...` fingerprints which the host key must match.
+    pub(crate) host_key_fingerprints: Vec<String>,
+    /// The hosts to jump through, in order. The attributes of each jump host hold the jump hosts
+    /// before it.
+    pub(crate) jump_hosts:            Vec<SshUserHost>,
+}
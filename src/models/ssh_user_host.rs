@@ -1,33 +1,64 @@
-use std::fmt::{self, Display, Formatter};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display, Formatter},
+    hash::{Hash, Hasher},
+    net::Ipv6Addr,
+};
 
 use regex::Regex;
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+use crate::models::SshHostAttributes;
+
+/// `user@host:port`, where the user and the port are optional so that `ssh` takes them from
+/// `~/.ssh/config` (e.g. a bare alias like `web1`). An IPv6 address is written in brackets, like
+/// `deploy@[2001:db8::1]:2222`, and stored without them.
+#[derive(Debug, Clone)]
 pub(crate) struct SshUserHost {
-    user: String,
-    host: String,
-    port: u16,
+    user:       Option<String>,
+    host:       String,
+    port:       Option<u16>,
+    attributes: SshHostAttributes,
 }
 
 impl SshUserHost {
     pub(crate) fn parse_str<S: AsRef<str>>(s: S) -> Result<Self, ()> {
         let s = s.as_ref();
 
-        let regex = Regex::new(r"^([^/\s]+)@([^/\s:]+)(?::([0-9]{1,5}))?$").unwrap();
+        // no part can start with `-`, which `ssh` would take as an option
+        let regex = Regex::new(
+            r"(?x)
+            ^
+            (?: ([^-/\s@][^/\s@]*) @ )?         # user
+            (?:
+                \[ ([0-9A-Fa-f.:]+) \]          # IPv6 address in brackets
+                | ([^-/\s@:\[\]][^/\s@:\[\]]*)  # host name, alias or IPv4 address
+            )
+            (?: : ([0-9]{1,5}) )?               # port
+            $",
+        )
+        .unwrap();
 
         let result = regex.captures(s).ok_or(())?;
 
-        let user = result.get(1).unwrap().as_str();
-        let host = result.get(2).unwrap().as_str();
-        let port = match result.get(3) {
+        let user = result.get(1).map(|user| String::from(user.as_str()));
+        let host = match result.get(2) {
+            Some(ipv6) => {
+                ipv6.as_str().parse::<Ipv6Addr>().map_err(|_| ())?;
+
+                ipv6.as_str()
+            },
+            None => result.get(3).unwrap().as_str(),
+        };
+        let port = match result.get(4) {
             Some(port) => Some(port.as_str().parse::<u16>().map_err(|_| ())?),
             None => None,
         };
 
         Ok(SshUserHost {
-            user: String::from(user),
+            user,
             host: String::from(host),
-            port: port.unwrap_or(22),
+            port,
+            attributes: SshHostAttributes::default(),
         })
     }
 }
@@ -35,8 +66,8 @@ impl SshUserHost {
 impl SshUserHost {
     #[allow(dead_code)]
     #[inline]
-    pub(crate) fn get_user(&self) -> &str {
-        self.user.as_str()
+    pub(crate) fn get_user(&self) -> Option<&str> {
+        self.user.as_deref()
     }
 
     #[allow(dead_code)]
@@ -45,24 +76,188 @@ impl SshUserHost {
         self.host.as_str()
     }
 
+    /// `None` means the port in `~/.ssh/config`, or 22.
     #[inline]
-    pub(crate) fn get_port(&self) -> u16 {
+    pub(crate) fn get_port(&self) -> Option<u16> {
         self.port
     }
 
+    #[inline]
+    pub(crate) fn get_attributes(&self) -> &SshHostAttributes {
+        &self.attributes
+    }
+
+    #[inline]
+    pub(crate) fn set_attributes(&mut self, attributes: SshHostAttributes) {
+        self.attributes = attributes;
+    }
+
+    /// The destination for `ssh`, which takes an IPv6 address without brackets.
     #[inline]
     pub(crate) fn user_host(&self) -> String {
-        format!("{}@{}", self.user, self.host)
+        match self.user.as_deref() {
+            Some(user) => format!("{user}@{host}", host = self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// The destination for `scp` and URLs, in which an IPv6 address needs brackets.
+    #[inline]
+    pub(crate) fn user_bracketed_host(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{host}]", host = self.host)
+        } else {
+            self.host.clone()
+        };
+
+        match self.user.as_deref() {
+            Some(user) => format!("{user}@{host}"),
+            None => host,
+        }
+    }
+}
+
+// The attributes do not identify a host, so they are ignored when comparing.
+
+impl PartialEq for SshUserHost {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.user == other.user && self.host == other.host && self.port == other.port
+    }
+}
+
+impl Eq for SshUserHost {}
+
+impl PartialOrd for SshUserHost {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SshUserHost {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.user, &self.host, self.port).cmp(&(&other.user, &other.host, other.port))
+    }
+}
+
+impl Hash for SshUserHost {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.user.hash(state);
+        self.host.hash(state);
+        self.port.hash(state);
     }
 }
 
 impl Display for SshUserHost {
     #[inline]
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
-        if self.port != 22 {
-            f.write_fmt(format_args!("{}@{}:{}", self.user, self.host, self.port))
-        } else {
-            f.write_fmt(format_args!("{}@{}", self.user, self.host))
+        f.write_str(self.user_bracketed_host().as_str())?;
+
+        if let Some(port) = self.port {
+            f.write_fmt(format_args!(":{port}"))?;
         }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_user_host() {
+        let ssh_user_host = SshUserHost::parse_str("deploy@web1.example.com").unwrap();
+
+        assert_eq!(Some("deploy"), ssh_user_host.get_user());
+        assert_eq!("web1.example.com", ssh_user_host.get_host());
+        assert_eq!(None, ssh_user_host.get_port());
+        assert_eq!("deploy@web1.example.com", ssh_user_host.to_string());
+    }
+
+    #[test]
+    fn parse_bare_alias() {
+        let ssh_user_host = SshUserHost::parse_str("web1").unwrap();
+
+        assert_eq!(None, ssh_user_host.get_user());
+        assert_eq!("web1", ssh_user_host.get_host());
+        assert_eq!(None, ssh_user_host.get_port());
+        assert_eq!("web1", ssh_user_host.to_string());
+    }
+
+    #[test]
+    fn parse_without_user() {
+        let ssh_user_host = SshUserHost::parse_str("192.168.1.10:2222").unwrap();
+
+        assert_eq!(None, ssh_user_host.get_user());
+        assert_eq!("192.168.1.10", ssh_user_host.get_host());
+        assert_eq!(Some(2222), ssh_user_host.get_port());
+        assert_eq!("192.168.1.10:2222", ssh_user_host.to_string());
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        let ssh_user_host = SshUserHost::parse_str("deploy@[2001:db8::1]:2222").unwrap();
+
+        assert_eq!(Some("deploy"), ssh_user_host.get_user());
+        assert_eq!("2001:db8::1", ssh_user_host.get_host());
+        assert_eq!(Some(2222), ssh_user_host.get_port());
+        assert_eq!("deploy@2001:db8::1", ssh_user_host.user_host());
+        assert_eq!("deploy@[2001:db8::1]:2222", ssh_user_host.to_string());
+
+        let ssh_user_host = SshUserHost::parse_str("[::1]").unwrap();
+
+        assert_eq!("::1", ssh_user_host.get_host());
+        assert_eq!(None, ssh_user_host.get_port());
+        assert_eq!("[::1]", ssh_user_host.to_string());
+    }
+
+    #[test]
+    fn parse_ipv6_without_brackets() {
+        assert!(SshUserHost::parse_str("2001:db8::1").is_err());
+        assert!(SshUserHost::parse_str("deploy@2001:db8::1").is_err());
+        assert!(SshUserHost::parse_str("::1").is_err());
+    }
+
+    #[test]
+    fn parse_invalid_ipv6() {
+        assert!(SshUserHost::parse_str("[:]").is_err());
+        assert!(SshUserHost::parse_str("[1:2]").is_err());
+        assert!(SshUserHost::parse_str("deploy@[2001:db8::1::2]").is_err());
+        assert!(SshUserHost::parse_str("[2001:db8::g]").is_err());
+        assert!(SshUserHost::parse_str("[]").is_err());
+    }
+
+    #[test]
+    fn parse_leading_dash() {
+        assert!(SshUserHost::parse_str("-oProxyCommand=sh").is_err());
+        assert!(SshUserHost::parse_str("-oProxyCommand=sh@host").is_err());
+        assert!(SshUserHost::parse_str("deploy@-oProxyCommand=sh").is_err());
+        assert!(SshUserHost::parse_str("deploy@-host:22").is_err());
+
+        // a dash which is not leading is fine
+        assert_eq!("web-1", SshUserHost::parse_str("deploy-bot@web-1").unwrap().get_host());
+    }
+
+    #[test]
+    fn parse_port() {
+        assert_eq!(Some(65535), SshUserHost::parse_str("deploy@host:65535").unwrap().get_port());
+        assert!(SshUserHost::parse_str("deploy@host:65536").is_err());
+        assert!(SshUserHost::parse_str("deploy@host:123456").is_err());
+        assert!(SshUserHost::parse_str("deploy@host:").is_err());
+        assert!(SshUserHost::parse_str("deploy@host:ssh").is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(SshUserHost::parse_str("").is_err());
+        assert!(SshUserHost::parse_str("deploy@").is_err());
+        assert!(SshUserHost::parse_str("@host").is_err());
+        assert!(SshUserHost::parse_str("a@b@host").is_err());
+        assert!(SshUserHost::parse_str("deploy@host/path").is_err());
+        assert!(SshUserHost::parse_str("deploy@my host").is_err());
     }
 }
@@ -0,0 +1,95 @@
+use std::{
+    collections::BTreeSet,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+use execute::{command_args, Execute};
+use tempfile::{Builder, TempDir};
+
+use crate::{constants::*, models::*};
+
+/// The directory of the ControlMaster sockets and the hosts which have used them in this run.
+struct SshMultiplexing {
+    directory:      TempDir,
+    ssh_user_hosts: BTreeSet<SshUserHost>,
+}
+
+static SSH_MULTIPLEXING: Mutex<Option<SshMultiplexing>> = Mutex::new(None);
+
+/// Get the `ControlPath` which is shared by the SSH and SCP connections to the host in this run.
+/// `None` means that connections cannot be multiplexed.
+fn get_ssh_control_path(ssh_user_host: &SshUserHost) -> Option<String> {
+    let mut multiplexing = SSH_MULTIPLEXING.lock().unwrap();
+
+    if multiplexing.is_none() {
+        // a Unix socket path is limited to 104 bytes on macOS, whose `$TMPDIR` is about 50 bytes
+        // long, so the sockets are put in `/tmp` to leave room for `%C` and the random suffix
+        // which ssh adds while it creates a socket
+        match Builder::new().prefix("gitlab-deploy-ssh-").tempdir_in("/tmp") {
+            Ok(directory) => {
+                *multiplexing = Some(SshMultiplexing {
+                    directory,
+                    ssh_user_hosts: BTreeSet::new(),
+                });
+            },
+            Err(err) => {
+                log::warn!("Cannot create a directory for SSH connection sharing: {err}");
+
+                return None;
+            },
+        }
+    }
+
+    let multiplexing = multiplexing.as_mut().unwrap();
+
+    // a host which is already in the set is kept as it is
+    multiplexing.ssh_user_hosts.insert(ssh_user_host.clone());
+
+    // %C is a hash of the local host, the remote host, the port and the user
+    Some(format!("{directory}/%C", directory = multiplexing.directory.path().to_string_lossy()))
+}
+
+/// Add the arguments which make `ssh` or `scp` share a connection to the host.
+pub(crate) fn add_ssh_multiplexing_args(command: &mut Command, ssh_user_host: &SshUserHost) {
+    if let Some(control_path) = get_ssh_control_path(ssh_user_host) {
+        command.args([
+            "-o",
+            "ControlMaster=auto",
+            "-o",
+            &format!("ControlPath={control_path}"),
+            "-o",
+            &format!("ControlPersist={SSH_CONTROL_PERSIST}"),
+        ]);
+    }
+}
+
+/// Stop the master connections which have been opened in this run and remove their sockets. If
+/// the program is killed before this, the masters still exit after `SSH_CONTROL_PERSIST` idle
+/// seconds.
+pub(crate) fn close_ssh_connections() {
+    let Some(multiplexing) = SSH_MULTIPLEXING.lock().unwrap().take() else {
+        return;
+    };
+
+    let control_path =
+        format!("{directory}/%C", directory = multiplexing.directory.path().to_string_lossy());
+
+    for ssh_user_host in multiplexing.ssh_user_hosts.iter() {
+        let mut command =
+            command_args!("ssh", "-O", "exit", "-o", format!("ControlPath={control_path}"),);
+
+        if let Some(port) = ssh_user_host.get_port() {
+            command.args(["-p", port.to_string().as_str()]);
+        }
+
+        command.arg(ssh_user_host.user_host());
+
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+
+        if let Err(err) = command.execute() {
+            log::debug!("Cannot close the SSH connection to {ssh_user_host}: {err}");
+        }
+    }
+}
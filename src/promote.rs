@@ -0,0 +1,368 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::anyhow;
+use execute::Execute;
+use tempfile::tempdir;
+
+use crate::{
+    archive::open_tarball,
+    artifact_cache::{ArtifactCache, MANIFEST_FILE},
+    checksum::check_sha256sums,
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    functions::*,
+    models::*,
+    shell::quote,
+};
+
+const DOCKER_COMPOSE_FILE: &str = "docker-compose.yml";
+
+/// The files of a release which are copied between phases, with their SHA-256 checksums.
+struct Artifact {
+    /// `<image>.tar.zst` or `<public_name>.tar.zst`, and `docker-compose.yml` for a backend
+    /// release.
+    files:    Vec<String>,
+    /// In the format of `sha256sum`.
+    checksum: String,
+}
+
+impl Artifact {
+    #[inline]
+    fn is_backend(&self) -> bool {
+        self.files.iter().any(|file| file == DOCKER_COMPOSE_FILE)
+    }
+
+    #[inline]
+    fn tarball_names(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().filter_map(|file| file.strip_suffix(".tar.zst"))
+    }
+}
+
+pub(crate) fn promote(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::Promote { .. }));
+
+    if let CLICommands::Promote {
+        gitlab_project_id: project_id,
+        commit_sha,
+        project_name,
+        reference_name,
+        from,
+        to,
+        parallel,
+        artifact_cache,
+        build_target,
+        artifact_cache_max_size,
+        artifact_cache_max_age,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        if from.as_ref() == to.as_ref() {
+            return Err(anyhow!("The phases to promote from and to should be different"));
+        }
+
+        let ssh_user_hosts = find_ssh_user_hosts(to, project_id)?;
+
+        if ssh_user_hosts.is_empty() {
+            log::warn!("No hosts to deploy!");
+            return Ok(());
+        }
+
+        let source_ssh_user_hosts = find_source_ssh_user_hosts(from.clone(), project_id)?;
+
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        let release = format!(
+            "{reference_name}-{commit_sha}",
+            reference_name = reference_name.as_ref(),
+            commit_sha = commit_sha.get_short_sha(),
+        );
+
+        let temp_dir = tempdir()?;
+
+        let cached_artifact = match artifact_cache {
+            Some(directory) => {
+                ArtifactCache::new(directory, artifact_cache_max_size, artifact_cache_max_age)
+                    .get(project_id, &commit_sha, build_target.as_ref())?
+                    .map(read_cached_artifact)
+                    .transpose()?
+            },
+            None => None,
+        };
+
+        let (artifact_dir, artifact) = match cached_artifact {
+            Some(cached_artifact) => cached_artifact,
+            None => {
+                let artifact = fetch_artifact(
+                    &source_ssh_user_hosts,
+                    project.as_str(),
+                    release.as_str(),
+                    temp_dir.path(),
+                )?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The release {release} cannot be found on any host of the phase {from}",
+                        from = from.as_ref()
+                    )
+                })?;
+
+                (temp_dir.path().to_path_buf(), artifact)
+            },
+        };
+
+        run_on_hosts(&ssh_user_hosts, parallel, OnError::Continue, |ssh_user_host| {
+            log::info!("Deploying to {ssh_user_host}");
+
+            let ssh_project = format!(
+                "{ssh_home}/{PROJECT_DIRECTORY}/{project}/{release}",
+                ssh_home = get_ssh_home(ssh_user_host)?,
+            );
+
+            let command_in_ssh =
+                format!("mkdir -p {ssh_project}", ssh_project = quote(&ssh_project));
+
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
+                    return Err(anyhow!(
+                        "Cannot create the directory {ssh_project:?} for storing the release."
+                    ));
+                }
+            }
+
+            for file in artifact.files.iter() {
+                let ssh_file_path = format!("{ssh_project}/{file}");
+
+                if plan_upload(ssh_user_host, file.as_str(), ssh_file_path.as_str()) {
+                    continue;
+                }
+
+                let mut command =
+                    create_scp_command(ssh_user_host, file.as_str(), ssh_file_path.as_str());
+
+                command.current_dir(artifact_dir.as_path());
+
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
+                    return Err(anyhow!(
+                        "Cannot copy {file:?} to {ssh_file_path:?} on {ssh_user_host}."
+                    ));
+                }
+            }
+
+            let command_in_ssh = format!(
+                "cd {ssh_project} && sha256sum -c --quiet -",
+                ssh_project = quote(&ssh_project)
+            );
+
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                let status =
+                    execute_prefixed_input_reader(&mut command, &mut artifact.checksum.as_bytes())?;
+
+                if !status.success() {
+                    return Err(anyhow!(
+                        "The files in {ssh_project:?} on {ssh_user_host} do not match the ones on \
+                         the phase {from}",
+                        from = from.as_ref()
+                    ));
+                }
+            }
+
+            if artifact.is_backend() {
+                load_back_images(
+                    ssh_user_host,
+                    ssh_project.as_str(),
+                    &artifact,
+                    artifact_dir.as_path(),
+                )?;
+            }
+
+            Ok(())
+        })?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
+
+/// Take the artifact from an entry of the artifact cache. The entry has been checked against its
+/// manifest already.
+fn read_cached_artifact(entry_path: PathBuf) -> anyhow::Result<(PathBuf, Artifact)> {
+    let manifest = fs::read_to_string(entry_path.join(MANIFEST_FILE))?;
+
+    let mut files = Vec::new();
+    let mut checksum = String::new();
+
+    // <checksum>  <file>
+    for line in manifest.lines() {
+        if let Some((_, file)) = line.split_once("  ") {
+            if file.ends_with(".tar.zst") || file == DOCKER_COMPOSE_FILE {
+                files.push(String::from(file));
+
+                checksum.push_str(line);
+                checksum.push('\n');
+            }
+        }
+    }
+
+    Ok((entry_path, Artifact {
+        files,
+        checksum,
+    }))
+}
+
+/// Download the artifact of the release from the first source host which has it. `None` is returned
+/// if no source host has the release.
+fn fetch_artifact(
+    source_ssh_user_hosts: &[SshUserHost],
+    project: &str,
+    release: &str,
+    directory: &Path,
+) -> anyhow::Result<Option<Artifact>> {
+    for ssh_user_host in source_ssh_user_hosts {
+        match fetch_artifact_from_host(ssh_user_host, project, release, directory) {
+            Ok(Some(artifact)) => return Ok(Some(artifact)),
+            Ok(None) => log::info!("{release} is not on {ssh_user_host}"),
+            Err(err) => log::warn!("{err}"),
+        }
+    }
+
+    Ok(None)
+}
+
+fn fetch_artifact_from_host(
+    ssh_user_host: &SshUserHost,
+    project: &str,
+    release: &str,
+    directory: &Path,
+) -> anyhow::Result<Option<Artifact>> {
+    let ssh_project = format!(
+        "{ssh_home}/{PROJECT_DIRECTORY}/{project}/{release}",
+        ssh_home = get_ssh_home(ssh_user_host)?,
+    );
+
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "if [ -d {ssh_project} ]; then cd {ssh_project} && find . -mindepth 1 -maxdepth 1 \
+             -type f \\( -name '*.tar.zst' -o -name {docker_compose_file} \\) -printf '%f\\0' | \
+             LC_ALL=C sort -z | xargs -0 -r sha256sum; fi",
+            ssh_project = quote(&ssh_project),
+            docker_compose_file = quote(DOCKER_COMPOSE_FILE),
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Cannot list the files in {ssh_project:?} of {ssh_user_host}"));
+    }
+
+    let checksum = String::from_utf8(output.stdout)?;
+
+    // <checksum>  <file>
+    let files: Vec<String> = checksum
+        .lines()
+        .filter_map(|line| line.split_once("  ").map(|(_, file)| String::from(file)))
+        .collect();
+
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    log::info!("Copying {release} from {ssh_user_host}");
+
+    for file in files.iter() {
+        let ssh_file_path = format!("{ssh_project}/{file}");
+
+        let mut command = create_ssh_command(
+            ssh_user_host,
+            format!("cat {ssh_file_path}", ssh_file_path = quote(&ssh_file_path)),
+        );
+
+        // `execute` would discard the output, so the command is run directly
+        command.stdout(File::create(directory.join(file))?);
+
+        if !command.status()?.success() {
+            return Err(anyhow!("Cannot copy {ssh_file_path:?} from {ssh_user_host}"));
+        }
+    }
+
+    if !check_sha256sums(directory, checksum.as_str())? {
+        return Err(anyhow!("The files copied from {ssh_user_host} are corrupted"));
+    }
+
+    Ok(Some(Artifact {
+        files,
+        checksum,
+    }))
+}
+
+/// Load the docker images of a backend release. A release deployed with `--docker-registry` has no
+/// image tarballs, so its images are pulled from the registry instead.
+fn load_back_images(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+    artifact: &Artifact,
+    directory: &Path,
+) -> anyhow::Result<()> {
+    let mut tarball_names = artifact.tarball_names().peekable();
+
+    if tarball_names.peek().is_none() {
+        if plan_ssh(ssh_user_host, "docker image pull <the images of docker-compose.yml>") {
+            return Ok(());
+        }
+
+        for image_tag in list_ssh_release_images(ssh_user_host, ssh_project)? {
+            let command_in_ssh =
+                format!("docker image pull {image_tag}", image_tag = quote(&image_tag));
+
+            let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+            let status = execute_prefixed(&mut command)?;
+
+            if !status.success() {
+                return Err(anyhow!("Cannot pull the docker image {image_tag}"));
+            }
+        }
+
+        return Ok(());
+    }
+
+    for name in tarball_names {
+        let tarball_path: PathBuf = directory.join(format!("{name}.tar.zst"));
+
+        if plan_ssh(ssh_user_host, format!("docker image load < {}", tarball_path.display())) {
+            continue;
+        }
+
+        log::info!("Extracting {name}.tar.zst");
+
+        let mut reader = open_tarball(directory, name)?;
+
+        let mut command = create_ssh_command(ssh_user_host, "docker image load");
+
+        let status = execute_prefixed_input_reader(&mut command, &mut reader)?;
+
+        if !status.success() {
+            return Err(anyhow!("Cannot deploy the docker image {name}"));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashSet, num::NonZeroUsize};
+
+use anyhow::anyhow;
+use execute::Execute;
+
+use crate::{
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    front_control::get_front_release_directory_name,
+    functions::*,
+    models::*,
+    shell::quote,
+};
+
+pub(crate) fn prune(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::Prune { .. }));
+
+    if let CLICommands::Prune {
+        gitlab_project_id: project_id,
+        project_name,
+        phase,
+        keep,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
+
+        if ssh_user_hosts.is_empty() {
+            log::warn!("No hosts to prune!");
+            return Ok(());
+        }
+
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Abort, |ssh_user_host| {
+            log::info!("Pruning {ssh_user_host}");
+
+            let ssh_home = get_ssh_home(ssh_user_host)?;
+
+            let ssh_project_root = format!("{ssh_home}/{PROJECT_DIRECTORY}/{project}");
+
+            let releases = list_ssh_releases(ssh_user_host, ssh_project_root.as_str())?;
+
+            let last_up = read_ssh_last_up(ssh_user_host, ssh_project_root.as_str())?;
+
+            let last_apply = read_ssh_control_log(ssh_user_host, ssh_project_root.as_str())?
+                .into_iter()
+                .rev()
+                .find(|(command, _)| command == "apply")
+                .map(|(_, release)| release);
+
+            let is_active = |release: &String| {
+                last_up.as_ref() == Some(release) || last_apply.as_ref() == Some(release)
+            };
+
+            // releases of the same commit but different references share image tags, so the tags
+            // which a kept release still uses are not removed
+            let mut kept_image_tags = HashSet::new();
+
+            for (index, release) in releases.iter().enumerate() {
+                if index < keep || is_active(release) {
+                    kept_image_tags.extend(list_ssh_release_images(
+                        ssh_user_host,
+                        format!("{ssh_project_root}/{release}").as_str(),
+                    )?);
+                }
+            }
+
+            for release in releases.iter().skip(keep) {
+                if is_active(release) {
+                    log::info!("Keeping {release} because it is active");
+                    continue;
+                }
+
+                let ssh_project = format!("{ssh_project_root}/{release}");
+
+                let image_tags = list_ssh_release_images(ssh_user_host, ssh_project.as_str())?;
+
+                for image_tag in image_tags {
+                    if kept_image_tags.contains(&image_tag) {
+                        log::info!(
+                            "Keeping the docker image {image_tag} because a kept release uses it"
+                        );
+                        continue;
+                    }
+
+                    let command_in_ssh =
+                        format!("docker image rm {image_tag}", image_tag = quote(&image_tag));
+
+                    if plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                        continue;
+                    }
+
+                    log::info!("Removing the docker image {image_tag}");
+
+                    let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                    let status = command.execute()?;
+
+                    if let Some(0) = status {
+                        // do nothing
+                    } else {
+                        log::warn!("The docker image {image_tag} cannot be removed");
+                    }
+                }
+
+                // the public static files extracted by `frontend-control`, whatever the public
+                // name is
+                let command_in_ssh = format!(
+                    "rm -rf {ssh_www_path}/*/releases/{name} {ssh_www_path}/*/releases/{name}.tmp",
+                    ssh_www_path = quote(&format!("{ssh_home}/{SERVICE_DIRECTORY}/www")),
+                    name = quote(&get_front_release_directory_name(&project, release)),
+                );
+
+                if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                    let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                    let status = command.execute()?;
+
+                    if let Some(0) = status {
+                        // do nothing
+                    } else {
+                        log::warn!("The public static files of {release} cannot be removed");
+                    }
+                }
+
+                let command_in_ssh =
+                    format!("rm -r {ssh_project}", ssh_project = quote(&ssh_project));
+
+                if plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                    continue;
+                }
+
+                log::info!("Removing {ssh_project:?}");
+
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                let status = command.execute()?;
+
+                if let Some(0) = status {
+                    // do nothing
+                } else {
+                    return Err(anyhow!("Cannot remove {ssh_project:?}"));
+                }
+            }
+
+            Ok(())
+        })?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
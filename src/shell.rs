@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+
+/// Quote `s` as a single word for a POSIX shell. Words which only consist of characters that
+/// the shell never interprets are returned as they are, and anything else is wrapped in single
+/// quotes (a single quote in `s` becomes `'"'"'`). No backslashes are added, so the result can also
+/// be nested in an option of `ssh` (e.g. `ProxyCommand`), whose own tokenizer treats a backslash in
+/// quotes as an escape.
+pub(crate) fn quote(s: &str) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut quoted = String::with_capacity(s.len() + 2);
+
+    quoted.push('\'');
+
+    for c in s.chars() {
+        if c == '\'' {
+            quoted.push_str("'\"'\"'");
+        } else {
+            quoted.push(c);
+        }
+    }
+
+    quoted.push('\'');
+
+    Cow::Owned(quoted)
+}
+
+/// Quote every argument by [`quote`] and join them with spaces, so that the remote shell gets the
+/// same arguments back.
+pub(crate) fn join<I: IntoIterator<Item = S>, S: AsRef<str>>(args: I) -> String {
+    args.into_iter().map(|arg| quote(arg.as_ref()).into_owned()).collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_safe() {
+        assert_eq!("abc-1.2_3", quote("abc-1.2_3"));
+        assert_eq!("user@host:/srv/a,b+c=d%", quote("user@host:/srv/a,b+c=d%"));
+        assert!(matches!(quote("abc"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn quote_leading_dash() {
+        // a leading dash is not interpreted by the shell, only by the command
+        assert_eq!("-rf", quote("-rf"));
+        assert_eq!("--help", quote("--help"));
+    }
+
+    #[test]
+    fn quote_empty() {
+        assert_eq!("''", quote(""));
+    }
+
+    #[test]
+    fn quote_single_quote() {
+        assert_eq!(r#"'it'"'"'s'"#, quote("it's"));
+        assert_eq!(r#"''"'"''"#, quote("'"));
+    }
+
+    #[test]
+    fn quote_expansions() {
+        assert_eq!("'$HOME'", quote("$HOME"));
+        assert_eq!("'${HOME}/a'", quote("${HOME}/a"));
+        assert_eq!("'`id`'", quote("`id`"));
+        assert_eq!("'$(id)'", quote("$(id)"));
+    }
+
+    #[test]
+    fn quote_backslash() {
+        assert_eq!(r"'a\b'", quote(r"a\b"));
+        assert_eq!(r"'\'", quote(r"\"));
+    }
+
+    #[test]
+    fn quote_whitespace() {
+        assert_eq!("'a b'", quote("a b"));
+        assert_eq!("'a\tb'", quote("a\tb"));
+        assert_eq!("'a\nb'", quote("a\nb"));
+    }
+
+    #[test]
+    fn quote_glob_and_operators() {
+        assert_eq!("'*'", quote("*"));
+        assert_eq!("'a;b'", quote("a;b"));
+        assert_eq!("'a|b&c'", quote("a|b&c"));
+        assert_eq!("'~'", quote("~"));
+    }
+
+    #[test]
+    fn join_args() {
+        assert_eq!("", join::<[&str; 0], &str>([]));
+        assert_eq!("rm -rf '' 'a b'", join(["rm", "-rf", "", "a b"]));
+        assert_eq!(
+            r#"echo '$HOME' '`id`' 'a\b' 'it'"'"'s' 'a
+b'"#,
+            join(["echo", "$HOME", "`id`", r"a\b", "it's", "a\nb"])
+        );
+        assert_eq!("a b", join(vec![String::from("a"), String::from("b")]));
+    }
+}
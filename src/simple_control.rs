@@ -1,12 +1,13 @@
-use std::fmt::Write;
+use std::{fmt::Write, num::NonZeroUsize};
 
 use anyhow::anyhow;
-use execute::Execute;
 
 use crate::{
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    models::OnError,
+    shell::{join, quote},
 };
 
 pub(crate) fn simple_control(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -22,6 +23,7 @@ pub(crate) fn simple_control(cli_args: CLIArgs) -> anyhow::Result<()> {
         gitlab_api_token: _,
         inject_project_directory,
         command,
+        ..
     } = cli_args.command
     {
         check_ssh()?;
@@ -35,7 +37,7 @@ pub(crate) fn simple_control(cli_args: CLIArgs) -> anyhow::Result<()> {
             return Ok(());
         }
 
-        for ssh_user_host in ssh_user_hosts.iter() {
+        run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Abort, |ssh_user_host| {
             log::info!("Controlling to {ssh_user_host} ({command_string})");
 
             let ssh_root = {
@@ -55,56 +57,53 @@ pub(crate) fn simple_control(cli_args: CLIArgs) -> anyhow::Result<()> {
 
             {
                 let command_in_ssh = if inject_project_directory {
-                    let mut command_in_ssh =
-                        String::with_capacity(command_string.len() + ssh_project.len() + 1);
-
-                    if command[0] == "sudo" {
-                        command_in_ssh.push_str("sudo ");
-
-                        if command.len() > 1 {
-                            command_in_ssh.push_str(&command[1]);
-                            command_in_ssh.write_fmt(format_args!(" {ssh_project:?} "))?;
-                            command_in_ssh.push_str(&command[2..].join(" "));
-                        }
-                    } else {
-                        command_in_ssh.push_str(&command[0]);
-                        command_in_ssh.write_fmt(format_args!(" {ssh_project:?} "))?;
-                        command_in_ssh.push_str(&command[1..].join(" "));
+                    let mut args: Vec<&str> = command.iter().map(String::as_str).collect();
+
+                    if command[0] != "sudo" {
+                        args.insert(1, ssh_project.as_str());
+                    } else if command.len() > 1 {
+                        args.insert(2, ssh_project.as_str());
                     }
 
-                    command_in_ssh
+                    join(args)
                 } else {
-                    command_string.clone()
+                    join(&command)
                 };
 
-                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+                if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                    let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                let output = command.execute_output()?;
+                    let status = execute_prefixed(&mut command)?;
 
-                if !output.status.success() {
-                    return Err(anyhow!("Control failed!"));
+                    if !status.success() {
+                        return Err(anyhow!("Control failed!"));
+                    }
                 }
             }
 
-            {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!(
-                        "cd {ssh_project:?} && echo \"{timestamp} {command_string:?} \
-                         {reference_name}-{commit_sha}\" >> {ssh_project:?}/../control.log",
-                        timestamp = current_timestamp(),
-                        reference_name = reference_name.as_ref(),
-                        commit_sha = commit_sha.get_short_sha(),
-                    ),
-                );
-
-                let output = command.execute_output()?;
-
-                if !output.status.success() {
+            let command_in_ssh = format!(
+                "cd {ssh_project} && printf '%s\\n' {line} >> {ssh_project}/../control.log",
+                ssh_project = quote(&ssh_project),
+                line = quote(&format!(
+                    "{timestamp} {command_string:?} {reference_name}-{commit_sha}",
+                    timestamp = current_timestamp(),
+                    reference_name = reference_name.as_ref(),
+                    commit_sha = commit_sha.get_short_sha(),
+                )),
+            );
+
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
+
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
                     return Err(anyhow!("Control failed!"));
                 }
             }
-        }
+
+            Ok(())
+        })?;
 
         log::info!("Successfully!");
     }
@@ -1,13 +1,14 @@
 use std::{fmt::Write, fs::File};
 
 use anyhow::anyhow;
-use execute::Execute;
 use tempfile::tempdir;
 
 use crate::{
     cli::{CLIArgs, CLICommands},
     constants::*,
     functions::*,
+    models::OnError,
+    shell::quote,
 };
 
 pub(crate) fn simple_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
@@ -21,10 +22,12 @@ pub(crate) fn simple_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
         phase,
         gitlab_api_url_prefix: api_url_prefix,
         gitlab_api_token: api_token,
+        gitlab_api_ca_file: ca_file,
+        parallel,
+        ..
     } = cli_args.command
     {
         check_ssh()?;
-        check_wget()?;
         check_docker()?;
 
         let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
@@ -36,10 +39,16 @@ pub(crate) fn simple_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
 
         let temp_dir = tempdir()?;
 
-        let archive_file_path =
-            download_archive(&temp_dir, api_url_prefix, api_token, project_id, &commit_sha)?;
+        let archive_file_path = download_archive(
+            &temp_dir,
+            api_url_prefix,
+            api_token,
+            ca_file.as_deref(),
+            project_id,
+            &commit_sha,
+        )?;
 
-        for ssh_user_host in ssh_user_hosts.iter() {
+        run_on_hosts(&ssh_user_hosts, parallel, OnError::Continue, |ssh_user_host| {
             log::info!("Deploying to {ssh_user_host}");
 
             let ssh_root = {
@@ -57,15 +66,15 @@ pub(crate) fn simple_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
                 commit_sha = commit_sha.get_short_sha(),
             );
 
-            {
-                let mut command =
-                    create_ssh_command(ssh_user_host, format!("mkdir -p {ssh_project:?}"));
+            let command_in_ssh =
+                format!("mkdir -p {ssh_project}", ssh_project = quote(&ssh_project));
 
-                let status = command.execute()?;
+            if !plan_ssh(ssh_user_host, command_in_ssh.as_str()) {
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                let status = execute_prefixed(&mut command)?;
+
+                if !status.success() {
                     return Err(anyhow!(
                         "Cannot create the directory {ssh_project:?} for storing the project \
                          files.",
@@ -73,24 +82,28 @@ pub(crate) fn simple_deploy(cli_args: CLIArgs) -> anyhow::Result<()> {
                 }
             }
 
-            log::info!("Unpacking the archive file");
+            let command_in_ssh = format!(
+                "tar --strip-components 1 -x -v -f - -C {ssh_project}",
+                ssh_project = quote(&ssh_project)
+            );
+
+            if !plan_ssh(ssh_user_host, format!("{command_in_ssh} < archive.tar")) {
+                log::info!("Unpacking the archive file");
 
-            {
-                let mut command = create_ssh_command(
-                    ssh_user_host,
-                    format!("tar --strip-components 1 -x -v -f - -C {ssh_project:?}"),
-                );
+                let mut command = create_ssh_command(ssh_user_host, command_in_ssh);
 
-                let status =
-                    command.execute_input_reader(&mut File::open(archive_file_path.as_path())?)?;
+                let status = execute_prefixed_input_reader(
+                    &mut command,
+                    &mut File::open(archive_file_path.as_path())?,
+                )?;
 
-                if let Some(0) = status {
-                    // do nothing
-                } else {
+                if !status.success() {
                     return Err(anyhow!("Cannot deploy the project"));
                 }
             }
-        }
+
+            Ok(())
+        })?;
 
         log::info!("Successfully!");
     }
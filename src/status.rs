@@ -0,0 +1,266 @@
+use std::process::Stdio;
+
+use anyhow::anyhow;
+use execute::Execute;
+use serde::Serialize;
+
+use crate::{
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    front_control::get_front_release_directory_name,
+    functions::*,
+    models::*,
+    shell::quote,
+};
+
+#[derive(Debug, Default, Serialize)]
+struct HostStatus {
+    host:       String,
+    /// The release in `last-up`, which is the active backend release.
+    last_up:    Option<String>,
+    /// The releases which the `html` symlinks of the public static files point at.
+    html:       Vec<HtmlStatus>,
+    /// The release directories, newest first.
+    releases:   Vec<String>,
+    /// The containers of the release in `last-up`.
+    containers: Vec<ContainerStatus>,
+    error:      Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HtmlStatus {
+    pub(crate) public_name: String,
+    pub(crate) release:     String,
+}
+
+#[derive(Debug, Serialize)]
+struct ContainerStatus {
+    service:   String,
+    state:     String,
+    health:    String,
+    exit_code: String,
+}
+
+pub(crate) fn status(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::Status { .. }));
+
+    if let CLICommands::Status {
+        gitlab_project_id: project_id,
+        project_name,
+        phase,
+        format,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
+
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        let mut host_statuses = Vec::with_capacity(ssh_user_hosts.len());
+
+        for ssh_user_host in ssh_user_hosts.iter() {
+            let mut host_status = HostStatus {
+                host: ssh_user_host.to_string(),
+                ..HostStatus::default()
+            };
+
+            // only read-only commands are run, so the status is also shown in dry-run mode
+            if let Err(err) = read_host_status(ssh_user_host, project.as_str(), &mut host_status) {
+                log::error!("{err}");
+
+                host_status.error = Some(err.to_string());
+            }
+
+            host_statuses.push(host_status);
+        }
+
+        match format {
+            OutputFormat::Table => print_table(&host_statuses),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&host_statuses)?),
+        }
+
+        let failed_count = host_statuses.iter().filter(|h| h.error.is_some()).count();
+
+        if failed_count > 0 {
+            return Err(anyhow!(
+                "Cannot get the status of {failed_count} of {count} hosts.",
+                count = host_statuses.len()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_host_status(
+    ssh_user_host: &SshUserHost,
+    project: &str,
+    host_status: &mut HostStatus,
+) -> anyhow::Result<()> {
+    let ssh_home = get_ssh_home(ssh_user_host)?;
+
+    let ssh_project_root = format!("{ssh_home}/{PROJECT_DIRECTORY}/{project}");
+
+    host_status.releases = list_ssh_releases(ssh_user_host, ssh_project_root.as_str())?;
+    host_status.last_up = read_ssh_last_up(ssh_user_host, ssh_project_root.as_str())?;
+    host_status.html = read_ssh_html_links(ssh_user_host, ssh_home.as_str(), project)?;
+
+    if let Some(last_up) = host_status.last_up.as_deref() {
+        host_status.containers =
+            read_ssh_containers(ssh_user_host, format!("{ssh_project_root}/{last_up}").as_str())?;
+    }
+
+    Ok(())
+}
+
+/// Read the `html` symlinks in `services/www/*` which point at an extracted release of the project.
+pub(crate) fn read_ssh_html_links(
+    ssh_user_host: &SshUserHost,
+    ssh_home: &str,
+    project: &str,
+) -> anyhow::Result<Vec<HtmlStatus>> {
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "for link in {ssh_www_path}/*/html; do if [ -L \"$link\" ]; then printf '%s %s\\n' \
+             \"$(basename \"$(dirname \"$link\")\")\" \"$(readlink \"$link\")\"; fi; done",
+            ssh_www_path = quote(&format!("{ssh_home}/{SERVICE_DIRECTORY}/www")),
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Cannot read the public static files of {ssh_user_host}"));
+    }
+
+    // `get_front_release_directory_name` with an empty release gives the prefix
+    let prefix = format!("releases/{name}", name = get_front_release_directory_name(project, ""));
+
+    let html = String::from_utf8(output.stdout)?
+        .lines()
+        .filter_map(|line| {
+            let (public_name, target) = line.split_once(' ')?;
+
+            Some(HtmlStatus {
+                public_name: String::from(public_name),
+                release:     String::from(target.strip_prefix(prefix.as_str())?),
+            })
+        })
+        .collect();
+
+    Ok(html)
+}
+
+/// Read the containers of the release in `ssh_project` with `docker compose ps`. A release without
+/// a docker compose file has no containers.
+fn read_ssh_containers(
+    ssh_user_host: &SshUserHost,
+    ssh_project: &str,
+) -> anyhow::Result<Vec<ContainerStatus>> {
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "cd {ssh_project} && if [ -f docker-compose.yml ]; then docker compose ps --all \
+             --format {format}; fi",
+            ssh_project = quote(ssh_project),
+            format = quote(COMPOSE_PS_FORMAT),
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Cannot list the containers in {ssh_project:?} of {ssh_user_host}"));
+    }
+
+    let containers = String::from_utf8(output.stdout)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.split(' ').map(String::from);
+
+            ContainerStatus {
+                service:   fields.next().unwrap_or_default(),
+                state:     fields.next().unwrap_or_default(),
+                health:    fields.next().unwrap_or_default(),
+                exit_code: fields.next().unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    Ok(containers)
+}
+
+fn print_table(host_statuses: &[HostStatus]) {
+    let rows: Vec<[String; 5]> = host_statuses
+        .iter()
+        .map(|host_status| {
+            let html = host_status
+                .html
+                .iter()
+                .map(|html| format!("{}={}", html.public_name, html.release))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let containers = host_status
+                .containers
+                .iter()
+                .map(|container| match (container.state.as_str(), container.health.as_str()) {
+                    ("exited", _) => {
+                        format!("{} exited({})", container.service, container.exit_code)
+                    },
+                    (state, "") => format!("{} {state}", container.service),
+                    (state, health) => format!("{} {state}({health})", container.service),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let last_up = match host_status.error.as_deref() {
+                Some(error) => format!("(error: {error})"),
+                None => host_status.last_up.clone().unwrap_or_else(|| String::from("-")),
+            };
+
+            [
+                host_status.host.clone(),
+                last_up,
+                if html.is_empty() { String::from("-") } else { html },
+                if host_status.releases.is_empty() {
+                    String::from("-")
+                } else {
+                    host_status.releases.join(", ")
+                },
+                if containers.is_empty() { String::from("-") } else { containers },
+            ]
+        })
+        .collect();
+
+    let header = ["HOST", "LAST UP", "HTML", "RELEASES", "CONTAINERS"].map(String::from);
+
+    let mut widths = [0; 5];
+
+    for row in [&header].into_iter().chain(rows.iter()) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    for row in [&header].into_iter().chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+
+        println!("{}", line.trim_end());
+    }
+}
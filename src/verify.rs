@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, num::NonZeroUsize, process::Stdio, sync::Mutex};
+
+use anyhow::anyhow;
+use execute::Execute;
+
+use crate::{
+    cli::{CLIArgs, CLICommands},
+    constants::*,
+    functions::*,
+    models::*,
+    shell::quote,
+    status::read_ssh_html_links,
+};
+
+const NONE: &str = "(none)";
+/// The fact of a host which cannot be inspected. Its other facts are unknown.
+const ERROR_FACT: &str = "error";
+const UNKNOWN: &str = "(unknown)";
+
+pub(crate) fn verify(cli_args: CLIArgs) -> anyhow::Result<()> {
+    debug_assert!(matches!(cli_args.command, CLICommands::Verify { .. }));
+
+    if let CLICommands::Verify {
+        gitlab_project_id: project_id,
+        project_name,
+        phase,
+        ..
+    } = cli_args.command
+    {
+        check_ssh()?;
+
+        let ssh_user_hosts = find_ssh_user_hosts(phase, project_id)?;
+
+        if ssh_user_hosts.is_empty() {
+            log::warn!("No hosts to verify!");
+            return Ok(());
+        }
+
+        let project = format!("{project_name}-{project_id}", project_name = project_name.as_ref());
+
+        // the facts of each host, in the order of the hosts
+        let host_facts: Mutex<Vec<(String, BTreeMap<String, String>)>> =
+            Mutex::new(Vec::with_capacity(ssh_user_hosts.len()));
+
+        // Only read-only commands are run, so the hosts are also verified in dry-run mode. A host
+        // which cannot be inspected still gets a column, with the error as its only fact, because
+        // the other hosts are worth comparing all the more then.
+        let result =
+            run_on_hosts(&ssh_user_hosts, NonZeroUsize::MIN, OnError::Continue, |ssh_user_host| {
+                log::info!("Inspecting {ssh_user_host}");
+
+                let (facts, result) = match read_host_facts(ssh_user_host, project.as_str()) {
+                    Ok(facts) => (facts, Ok(())),
+                    Err(err) => {
+                        let facts = BTreeMap::from([(String::from(ERROR_FACT), err.to_string())]);
+
+                        (facts, Err(err))
+                    },
+                };
+
+                host_facts.lock().unwrap().push((ssh_user_host.to_string(), facts));
+
+                result
+            });
+
+        let host_facts = host_facts.into_inner().unwrap();
+
+        let mut keys: Vec<&String> =
+            host_facts.iter().flat_map(|(_, facts)| facts.keys()).collect();
+
+        keys.sort();
+        keys.dedup();
+
+        let host_width = host_facts.iter().map(|(host, _)| host.len()).max().unwrap_or(0);
+
+        let mut drift_count = 0;
+
+        for key in keys {
+            let values: Vec<&str> = host_facts
+                .iter()
+                .map(|(_, facts)| match facts.get(key) {
+                    Some(value) => value.as_str(),
+                    None if facts.contains_key(ERROR_FACT) => UNKNOWN,
+                    None => NONE,
+                })
+                .collect();
+
+            if values.iter().all(|value| *value == values[0]) {
+                log::info!("{key}: {value}", value = values[0]);
+
+                continue;
+            }
+
+            drift_count += 1;
+
+            log::error!("{key} differs:");
+
+            for ((host, _), value) in host_facts.iter().zip(values) {
+                log::error!("  {host:<host_width$}  {value}");
+            }
+        }
+
+        if drift_count > 0 {
+            return Err(anyhow!(
+                "The hosts disagree on {drift_count} item{s}.",
+                s = if drift_count == 1 { "" } else { "s" }
+            ));
+        }
+
+        result?;
+
+        log::info!("Successfully!");
+    }
+
+    Ok(())
+}
+
+/// Read what the host runs for the project: the release in `last-up`, the image id of each docker
+/// image in that release, and the release and the checksum of each `html` symlink of the project.
+fn read_host_facts(
+    ssh_user_host: &SshUserHost,
+    project: &str,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let ssh_home = get_ssh_home(ssh_user_host)?;
+
+    let ssh_project_root = format!("{ssh_home}/{PROJECT_DIRECTORY}/{project}");
+
+    let mut facts = BTreeMap::new();
+
+    let last_up = read_ssh_last_up(ssh_user_host, ssh_project_root.as_str())?;
+
+    if let Some(last_up) = last_up.as_deref() {
+        let image_tags = list_ssh_release_images(
+            ssh_user_host,
+            format!("{ssh_project_root}/{last_up}").as_str(),
+        )?;
+
+        for (image_tag, image_id) in read_ssh_image_ids(ssh_user_host, &image_tags)? {
+            // keyed without the tag, so that hosts running different releases still line up
+            let repository = image_tag.rsplit_once(':').map(|(r, _)| r).unwrap_or(&image_tag);
+
+            facts.insert(format!("image {repository}"), image_id);
+        }
+    }
+
+    facts.insert(String::from("last-up"), last_up.unwrap_or_else(|| String::from(NONE)));
+
+    let ssh_www_path = format!("{ssh_home}/{SERVICE_DIRECTORY}/www");
+
+    for html in read_ssh_html_links(ssh_user_host, ssh_home.as_str(), project)? {
+        let checksum = read_ssh_tree_checksum(
+            ssh_user_host,
+            format!("{ssh_www_path}/{public_name}/html", public_name = html.public_name).as_str(),
+        )?;
+
+        facts.insert(format!("html {}", html.public_name), html.release);
+        facts.insert(format!("html {} checksum", html.public_name), checksum);
+    }
+
+    Ok(facts)
+}
+
+/// Read the id of each docker image on the host. A missing image gets `(none)`.
+fn read_ssh_image_ids(
+    ssh_user_host: &SshUserHost,
+    image_tags: &[String],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut image_ids = Vec::with_capacity(image_tags.len());
+
+    for image_tag in image_tags {
+        let mut command = create_ssh_command(
+            ssh_user_host,
+            format!(
+                "docker image inspect --format '{{{{.Id}}}}' {image_tag}",
+                image_tag = quote(image_tag)
+            ),
+        );
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::null());
+
+        let output = command.execute_output()?;
+
+        let image_id = if output.status.success() {
+            String::from_utf8(output.stdout)?.trim().to_string()
+        } else {
+            String::from(NONE)
+        };
+
+        image_ids.push((image_tag.clone(), image_id));
+    }
+
+    Ok(image_ids)
+}
+
+/// Compute one SHA-256 checksum of the paths and the contents of the files under `ssh_path`.
+fn read_ssh_tree_checksum(ssh_user_host: &SshUserHost, ssh_path: &str) -> anyhow::Result<String> {
+    let mut command = create_ssh_command(
+        ssh_user_host,
+        format!(
+            "cd {ssh_path} && find . -type f -print0 | LC_ALL=C sort -z | xargs -0 -r sha256sum | \
+             sha256sum",
+            ssh_path = quote(ssh_path)
+        ),
+    );
+
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    let output = command.execute_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Cannot compute the checksum of {ssh_path:?} on {ssh_user_host}"));
+    }
+
+    let output = String::from_utf8(output.stdout)?;
+
+    match output.split_whitespace().next() {
+        Some(checksum) => Ok(String::from(checksum)),
+        None => Err(anyhow!("Cannot compute the checksum of {ssh_path:?} on {ssh_user_host}")),
+    }
+}